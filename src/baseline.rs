@@ -0,0 +1,93 @@
+use crate::lua::CheckError;
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+/// A set of known findings that should not fail a check run.
+///
+/// Findings are matched by their data file, check file, and either their rule ID (if they have
+/// one) or their message.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Baseline {
+    findings: BTreeSet<BaselineEntry>,
+    /// The entries that have matched a finding in this run.
+    #[serde(skip)]
+    matched: BTreeSet<BaselineEntry>,
+}
+
+/// A single known finding in a [`Baseline`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub data_file: PathBuf,
+    pub check_file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The message of the finding. This is ignored for matching when there is an ID.
+    pub message: String,
+}
+
+impl BaselineEntry {
+    pub fn new(data_file: &Path, check_file: &Path, error: &CheckError) -> Self {
+        Self {
+            data_file: data_file.to_path_buf(),
+            check_file: check_file.to_path_buf(),
+            id: error.id.clone(),
+            message: error.error.clone(),
+        }
+    }
+
+    fn matches(&self, other: &Self) -> bool {
+        self.data_file == other.data_file
+            && self.check_file == other.check_file
+            && match (&self.id, &other.id) {
+                (Some(a), Some(b)) => a == b,
+                (None, None) => self.message == other.message,
+                _ => false,
+            }
+    }
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .wrap_err_with(|| format!("failed to read baseline file: {}", path.display()))?;
+        serde_json::from_slice(&contents)
+            .wrap_err_with(|| format!("failed to parse baseline file: {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents =
+            serde_json::to_vec_pretty(self).wrap_err("failed to serialize baseline to JSON")?;
+        std::fs::write(path, contents)
+            .wrap_err_with(|| format!("failed to write baseline file: {}", path.display()))
+    }
+
+    pub fn insert(&mut self, entry: BaselineEntry) {
+        self.findings.insert(entry);
+    }
+
+    /// Whether the finding is known in the baseline, without remembering the match.
+    pub fn contains(&self, finding: &BaselineEntry) -> bool {
+        self.findings.iter().any(|e| e.matches(finding))
+    }
+
+    /// Whether the finding is known in the baseline, and should therefore be suppressed.
+    /// The matching baseline entry is remembered so it isn't reported as stale.
+    pub fn suppresses(&mut self, finding: &BaselineEntry) -> bool {
+        match self.findings.iter().find(|e| e.matches(finding)) {
+            Some(entry) => {
+                self.matched.insert(entry.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The entries that haven't matched any finding so far, i.e. those that have been fixed.
+    pub fn stale(&self) -> impl Iterator<Item = &BaselineEntry> {
+        self.findings.difference(&self.matched)
+    }
+}
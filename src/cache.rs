@@ -0,0 +1,100 @@
+use crate::lua::SourceCode;
+use eyre::{Context, Result};
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+/// A cache of the findings of check files against data files, stored on disk.
+///
+/// Entries are keyed by a hash of both files' paths and contents, along with the checkonaut
+/// version and the options that may affect the findings. Anything else a check depends on, such
+/// as modules it `require`s or files it reads, is not part of the key; clear the cache directory
+/// or pass `--no-cache` if those change.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    /// Mixed into every key, so that e.g. a new checkonaut version never reuses old entries.
+    salt: String,
+}
+
+impl Cache {
+    /// The version of the format the findings are cached in, mixed into every key along with the
+    /// checkonaut version. Bump this whenever the cached findings change shape, e.g. version 2
+    /// added the number of check function invocations for `--strict`.
+    const FORMAT_VERSION: u32 = 2;
+
+    pub fn new(dir: PathBuf, salt: &str) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("failed to create cache directory: {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            salt: format!(
+                "{}\0{}\0{salt}",
+                env!("CARGO_PKG_VERSION"),
+                Self::FORMAT_VERSION
+            ),
+        })
+    }
+
+    /// The key of the findings for a check file against a data file with the given contents.
+    pub fn key(&self, data_file: &Path, data: &[u8], check: &SourceCode) -> String {
+        let mut hasher = Sha256::new();
+        for part in [
+            self.salt.as_bytes(),
+            data_file.as_os_str().as_encoded_bytes(),
+            data,
+            check.path.as_os_str().as_encoded_bytes(),
+            check.contents().as_bytes(),
+        ] {
+            // Prefix every part with its length, so that e.g. moving bytes from the path to the
+            // contents changes the key.
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .fold(String::with_capacity(64), |mut hex, b| {
+                let _ = write!(hex, "{b:02x}");
+                hex
+            })
+    }
+
+    /// The cached findings by the given key, if there are any.
+    ///
+    /// Unreadable entries are treated as missing.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let path = self.entry_path(key);
+        let contents = std::fs::read(&path).ok()?;
+        match serde_json::from_slice(&contents) {
+            Ok(findings) => Some(findings),
+            Err(error) => {
+                debug!(path = %path.display(), %error, "ignoring invalid cache entry");
+                None
+            }
+        }
+    }
+
+    /// Stores the findings by the given key. Failing to do so only warns, as the cache is only an
+    /// optimisation.
+    pub fn put<T: Serialize>(&self, key: &str, findings: &T) {
+        let path = self.entry_path(key);
+        let result = serde_json::to_vec(findings)
+            .wrap_err("failed to serialize findings")
+            .and_then(|contents| {
+                std::fs::write(&path, contents).wrap_err("failed to write cache entry")
+            });
+        if let Err(error) = result {
+            warn!(path = %path.display(), "failed to cache findings: {error:#}");
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        // Not `.json`, so that a cache directory among the inputs isn't checked as data.
+        self.dir.join(format!("{key}.cache"))
+    }
+}
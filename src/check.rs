@@ -1,112 +1,1172 @@
 use crate::{
-    file::{FileSearchResult, FileSearcher},
-    lua::{CheckError, CheckSeverity, SourceCode},
+    CancelledError, FindingsError,
+    baseline::{Baseline, BaselineEntry},
+    cache::Cache,
+    config::Config,
+    file::{
+        DataExtension, DataExtensions, DataFormat, FileSearchResult, FileSearcher, FormatOverride,
+        InMemoryData, expand_globs, is_gzip, is_test_file, retry_io,
+    },
+    fix::{self, Fix},
+    lua::{CheckError, CheckSeverity, LoadError, LuaOptions, SourceCode},
+    remote::{self, is_url},
 };
-use clap::Args;
+use clap::{Args, ValueEnum};
 use eyre::{Context, Result, bail, ensure, eyre};
+use flate2::read::GzDecoder;
+use globset::Glob;
+use indicatif::{ProgressBar, ProgressStyle};
 use mlua::{Lua, LuaSerdeExt};
+use notify::{RecursiveMode, Watcher};
 use rayon::prelude::*;
-use serde::Deserialize;
-use std::path::{Path, PathBuf};
-use tracing::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, field, info, info_span, warn};
 
 #[derive(Debug, Args)]
 pub struct Check {
     /// The data files or directories to check with.
     ///
-    /// Data files are files with the extensions `.json`, `.yml`, `.yaml`, or `.toml`.
-    /// Check files are files with the extension `.lua`. `_test.lua` files are ignored.
+    /// Data files are files with the extensions `.json`, `.jsons`, `.jsonl`, `.ndjson`, `.yml`,
+    /// `.yaml`, `.toml`, `.xml`, `.csv`, `.tsv`, `.hcl`, `.tf`, or `.env`, or any extension given
+    /// with `--data-ext`. Any of these may also be compressed with gzip, with a trailing `.gz`.
+    /// Check files are files with the extension `.lua`. `_test.lua` files are ignored, unless
+    /// `--no-ignore-tests` is given.
+    ///
+    /// Files starting with a period (`.`) are ignored by default. This includes files named just
+    /// `.env`, so checking those needs `--dotfiles`. Directories starting with a period (e.g.
+    /// `.github`) are walked, unless `--dotdirs=false` is given.
+    ///
+    /// Glob patterns (e.g. `configs/**/*.yaml`) are expanded, even if the shell doesn't.
+    ///
+    /// HTTP(S) URLs are fetched and checked as data files. Their format is detected from the
+    /// extension of the URL's path, or else from the `Content-Type` of the response.
     ///
-    /// Files starting with a period (`.`) are ignored by default.
-    #[arg(default_value = ".")]
+    /// Defaults to the current directory, unless `--input-list` is given.
     input: Vec<PathBuf>,
 
+    /// A document to check, given inline rather than in a file, e.g. `--data '{"foo": 1}'`.
+    ///
+    /// This may be given multiple times. Each is checked as a data file named `<inline>`, or
+    /// `<inline #N>` (counting from 0) if there are several. Without any other inputs, only these
+    /// are checked.
+    #[arg(long, value_name = "STRING")]
+    data: Vec<String>,
+
+    /// The format of the documents given with `--data`, e.g. `yaml`.
+    #[arg(long, value_name = "FORMAT", default_value = "json")]
+    data_format: DataFormat,
+
+    /// A file listing more inputs, one per line, as if they were given as arguments.
+    ///
+    /// Blank lines are skipped.
+    #[arg(long)]
+    input_list: Option<PathBuf>,
+
     /// Additional check files or directories to include.
     ///
-    /// These are not used for data; only check files are considered here.
+    /// These are not used for data; only check files are considered here. Glob patterns are
+    /// expanded as with the inputs.
     #[arg(short, long, alias("check"))]
     checks: Vec<PathBuf>,
 
     /// Enable processing of files starting with a period.
+    ///
+    /// Pass `--dotfiles=false` to turn this off when the configuration enables it.
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    dotfiles: Option<bool>,
+
+    /// Whether to descend into directories starting with a period, e.g. `.github`. They are by
+    /// default; pass `--dotdirs=false` to skip them, e.g. to stay out of `.git`.
+    ///
+    /// This is independent of `--dotfiles`: files starting with a period are still ignored within
+    /// these directories, unless `--dotfiles` is given as well.
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    dotdirs: Option<bool>,
+
+    /// Skip files and directories matching this glob, e.g. `vendor/**` or `*.generated.json`.
+    ///
+    /// The patterns are matched as if they were in a `.checkonautignore` file in every input:
+    /// against the path within the input, and against the plain file name.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<Glob>,
+
+    /// The minimum severity of a finding that causes the check to fail.
+    ///
+    /// Findings below this severity are still reported, but do not cause a non-zero exit.
+    ///
+    /// [default: error]
+    #[arg(long, value_enum)]
+    fail_on: Option<FailOn>,
+
+    /// Only produce output when there are findings.
+    ///
+    /// This does not affect the exit code or the reporting of findings.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// The maximum amount of memory, in bytes, that each Lua state may use.
+    ///
+    /// A check that exceeds this fails the run. A limit of 0 means unlimited.
+    #[arg(long, default_value_t = 0)]
+    memory_limit: usize,
+
+    /// Run each check once against all documents of all data files together, e.g. to check that
+    /// names are unique across files.
+    ///
+    /// The check function gets a sequence of `{ path = ..., index = ..., document = ... }` tables,
+    /// with `index` being the 0-based index of the document within its data file. Findings are
+    /// reported for `<aggregate>` rather than any one data file.
+    #[arg(long, conflicts_with_all = ["fix", "parallel_documents", "fail_fast"])]
+    aggregate: bool,
+
+    /// Check the documents of each data file in parallel, rather than one after another.
+    ///
+    /// This speeds up files with many documents, e.g. large multi-document YAML files, but every
+    /// document then gets its own Lua state, which uses more memory. Findings are still reported
+    /// in document order.
     #[arg(long)]
-    dotfiles: bool,
+    parallel_documents: bool,
+
+    /// A JSON value to pass to every check as `context.input`, e.g. deployment metadata.
+    ///
+    /// Prefix the value with `@` to read the JSON from that file instead.
+    #[arg(long, value_name = "JSON|@FILE", value_parser = parse_context_json)]
+    context_json: Option<serde_json::Value>,
+
+    /// How many more times to try reading a data or check file after an IO error that may be
+    /// transient, e.g. on a network filesystem.
+    ///
+    /// Missing files and parse errors are never retried.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    read_retries: u32,
+
+    /// How long to wait for each data file fetched from a URL, in seconds. Defaults to 30.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// The name of the global Lua function to call for each document.
+    ///
+    /// Functions whose names start with this name and an underscore (e.g. `Check_Naming`) are also
+    /// called, and their findings are tagged with the function name. Check files that don't define
+    /// any such function are not run.
+    #[arg(long, default_value = "Check")]
+    check_fn: String,
+
+    /// Read a single check from stdin, and run only that, skipping discovery of check files.
+    ///
+    /// Modules and files it reads are resolved relative to the current directory.
+    #[arg(
+        long,
+        alias("check-from-stdin"),
+        conflicts_with_all = ["only", "checks", "watch"]
+    )]
+    check_stdin: bool,
+
+    /// Warn about check files that fail to load, e.g. due to a syntax error, and skip them.
+    ///
+    /// By default, such a check file fails the run before any check runs.
+    #[arg(long)]
+    skip_broken_checks: bool,
+
+    /// Warn about every discovered check file (other than `_test.lua` files) that doesn't define
+    /// a check function, rather than silently skipping it as a library.
+    ///
+    /// This catches typos like `Chekc`, but also warns about libraries that checks `require`.
+    #[arg(long)]
+    warn_unused_checks: bool,
+
+    /// List the check files that would be run, then exit without checking any data.
+    #[arg(long)]
+    list_checks: bool,
+
+    /// List the data files that would be checked, grouped by format, then exit without running
+    /// any checks.
+    #[arg(long)]
+    list_data: bool,
+
+    /// Print the documents of this data file as the checks would receive them, as pretty JSON,
+    /// then exit without running any checks.
+    ///
+    /// This shows e.g. how dates or numbers in YAML and TOML are represented in Lua. The file may
+    /// also be a URL, or `<inline>` for a document given with `--data`.
+    #[arg(long, value_name = "FILE")]
+    dump_value: Option<PathBuf>,
+
+    /// Log how long each check took across all data files, and the slowest data files, after
+    /// checking.
+    ///
+    /// Checks whose findings were cached take no time.
+    #[arg(long)]
+    timings: bool,
+
+    /// Run the evaluation this many times and print the minimum, median, and maximum time it
+    /// took, e.g. to measure the effect of changes to the checks.
+    ///
+    /// Only the findings of the first run are reported. The cache is never used, so that every
+    /// run does the same work.
+    #[arg(
+        long,
+        hide = true,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["fix", "watch", "fail_fast", "write_baseline"],
+    )]
+    repeat: Option<u32>,
+
+    /// Also consider `_test.lua` files as check files.
+    ///
+    /// By default, test files are never run as checks. With this, any test file that defines a
+    /// check function is run like any other check file.
+    #[arg(long)]
+    no_ignore_tests: bool,
+
+    /// Keep running, re-running the checks whenever a check or data file changes.
+    ///
+    /// Findings never cause an exit in this mode. Press Ctrl-C to stop.
+    #[arg(long)]
+    watch: bool,
+
+    /// Only run these exact check files, skipping discovery of any others.
+    ///
+    /// Every file given must define the check function.
+    #[arg(long)]
+    only: Vec<PathBuf>,
+
+    /// Only run check files whose file name matches this glob, e.g. `*security*.lua`.
+    ///
+    /// If given multiple times, check files matching any of the globs are run.
+    #[arg(long)]
+    select: Vec<Glob>,
+
+    /// A baseline file of known findings to ignore.
+    ///
+    /// Findings in the baseline are not reported and do not fail the run. Baseline entries that no
+    /// longer match any finding are reported as stale, so they can be removed.
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// Write all current findings to the `--baseline` file, instead of failing on them.
+    #[arg(long, requires = "baseline")]
+    write_baseline: bool,
+
+    /// Parse files with a custom extension as a known data format, e.g. `conf=json`.
+    ///
+    /// Known formats are: json, jsons (a stream of JSON values), yaml, toml, xml, csv, tsv, hcl,
+    /// env, and markdown.
+    #[arg(long, value_name = "EXT=FORMAT")]
+    data_ext: Vec<DataExtension>,
+
+    /// Parse files whose path matches a glob as a known data format, e.g. `manifests/*.cfg=yaml`.
+    ///
+    /// The glob is matched against every trailing part of the path, so `manifests/*.cfg` matches
+    /// `deploy/manifests/a.cfg`. `*` doesn't match across directories, while `**` does. This takes
+    /// precedence over the built-in extensions and `--data-ext`; if several globs match, the first
+    /// one given wins.
+    #[arg(long, value_name = "GLOB=FORMAT")]
+    format_override: Vec<FormatOverride>,
+
+    /// Report data files that fail to parse as findings, and keep checking the other files.
+    ///
+    /// The run still fails if any data file failed to parse.
+    #[arg(long)]
+    continue_on_parse_error: bool,
+
+    /// Check each element of a JSON file's top-level array as a separate document.
+    ///
+    /// JSON files whose root is not an array are checked as a single document, as usual.
+    #[arg(long)]
+    json_array_as_documents: bool,
+
+    /// Split TOML files into separate documents at lines consisting of just this delimiter.
+    ///
+    /// Without a value, the delimiter is `+++`. Empty documents are skipped.
+    #[arg(
+        long,
+        value_name = "DELIMITER",
+        num_args = 0..=1,
+        default_missing_value = "+++"
+    )]
+    toml_multidoc: Option<String>,
+
+    /// Convert decimal numbers without a fractional part, e.g. `2.0`, into integers.
+    ///
+    /// Numbers are otherwise converted the same way for every format: integers that fit in 64
+    /// bits become Lua integers, and all other numbers become Lua floats. With this, `2.0` and `2`
+    /// are both integers, regardless of how the data file wrote them.
+    #[arg(long)]
+    normalize_numbers: bool,
+
+    /// Add the body of Markdown files to their front matter document, as the `__body` string.
+    #[arg(long)]
+    markdown_body: bool,
+
+    /// Check Markdown files without YAML front matter as an empty document.
+    ///
+    /// By default, such files have no documents, so they aren't checked at all.
+    #[arg(long)]
+    markdown_without_front_matter: bool,
+
+    /// Show a progress bar while checking data files.
+    ///
+    /// The bar is drawn on stderr, so it's only shown when stderr is a terminal.
+    #[arg(long)]
+    progress: bool,
+
+    /// A directory to cache findings in, to skip re-running checks on unchanged files.
+    ///
+    /// Findings are reused when the data file, the check file, the checkonaut version, and the
+    /// options for running checks are all unchanged. Without `--now`, they're only reused on the
+    /// same (UTC) day, as checks may depend on the current date. Changes to anything else a check
+    /// depends on, such as modules it `require`s or files it reads, are not detected; use
+    /// `--no-cache` or clear the directory if those change.
+    #[arg(long, env = "CHECKONAUT_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Don't use the `--cache-dir`, always running every check.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Fail the run if any data file wasn't checked at all, e.g. because it has no documents.
+    #[arg(long)]
+    strict: bool,
+
+    /// Also write the findings and summary as human-readable text to this file.
+    ///
+    /// The file is overwritten, and is written even when the run fails due to findings.
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Also write the totals of the run as a JSON object to this file, e.g. for dashboards.
+    ///
+    /// The object has the keys `errors`, `warnings`, `files_checked`, `checks_run`, and
+    /// `duration_ms`. Like `--report`, it is written even when the run fails due to findings.
+    #[arg(long, value_name = "PATH")]
+    summary_json: Option<PathBuf>,
+
+    /// Only check data files that differ from this git ref, e.g. `origin/main`.
+    ///
+    /// The changes are found with `git diff` in the repository containing the first input, along
+    /// with untracked files that git doesn't ignore. Check files are still discovered as usual.
+    #[arg(long, value_name = "REF")]
+    diff_base: Option<String>,
+
+    /// Stop checking more data files as soon as a finding fails the run.
+    ///
+    /// Which findings fail the run is decided by `--fail-on`, as usual. Data files that were
+    /// already being checked are still reported.
+    #[arg(long, alias("bail"), conflicts_with = "write_baseline")]
+    fail_fast: bool,
+
+    /// When interrupted with Ctrl-C, still report the findings of the data files checked so far,
+    /// including with `--report` and `--summary-json`, before exiting with code 130.
+    ///
+    /// Stale `--baseline` entries aren't reported, and `--write-baseline` writes nothing, as the
+    /// data files that weren't checked would look like they have no findings.
+    #[arg(long)]
+    flush_on_interrupt: bool,
+
+    /// Apply the fixes suggested by findings to the data files, then re-run the checks.
+    ///
+    /// This modifies the data files in place, keeping a backup of each changed file with an
+    /// `.orig` suffix. Only fixes of the form `{ replace = "...", with = "..." }` on findings with
+    /// a `line` can be applied; other findings are reported as usual.
+    #[arg(long, conflicts_with_all = ["watch", "write_baseline"])]
+    fix: bool,
+
+    /// Report at most this many findings, counting the rest without reporting them.
+    ///
+    /// The exit code still reflects all findings, including those not reported.
+    #[arg(long, value_name = "N")]
+    max_findings: Option<usize>,
+
+    /// How to print findings to stdout, in addition to logging them.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+
+    /// Print each finding to stdout as one tab-separated line of severity, data file, check file,
+    /// and message, e.g. for `cut` or `awk`.
+    ///
+    /// Findings without a check file (e.g. parse errors) have `-` in its place. Tabs, newlines,
+    /// and backslashes are escaped as `\t`, `\n`, and `\\`.
+    #[arg(long, conflicts_with = "format")]
+    plain: bool,
+
+    /// Write the output of `--format github`, `--plain`, `--list-checks`, or `--list-data` to this
+    /// file instead of stdout.
+    ///
+    /// Missing parent directories are created, and the file is overwritten. Use `--report` to
+    /// write the human findings to a file instead.
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(crate) lua: LuaOptions,
+}
+
+/// The minimum severity threshold at which findings fail a check run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailOn {
+    /// Never fail the run; findings are only reported.
+    None,
+    /// Fail the run on any warning or error.
+    Warning,
+    /// Fail the run only on errors.
+    #[default]
+    Error,
+}
+
+/// The format to print findings to stdout in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Only log findings, as human-readable text.
+    #[default]
+    Human,
+    /// Also print GitHub Actions workflow commands, so findings are annotated on the diff.
+    Github,
+}
+
+impl FailOn {
+    /// Whether a finding of the given severity should fail the run.
+    ///
+    /// Fatal findings always fail the run.
+    pub fn fails_on(self, severity: CheckSeverity) -> bool {
+        if severity == CheckSeverity::Fatal {
+            return true;
+        }
+        match self {
+            Self::None => false,
+            Self::Warning => true,
+            Self::Error => severity == CheckSeverity::Error,
+        }
+    }
 }
 
 impl Check {
+    /// Fills in the flags that weren't given on the command line from the configuration.
+    pub fn apply_config(&mut self, config: &Config) -> Result<()> {
+        self.dotfiles = self.dotfiles.or(config.dotfiles);
+        self.dotdirs = self.dotdirs.or(config.dotdirs);
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude_globs()?;
+        }
+        self.fail_on = self.fail_on.or(config.fail_on);
+        self.timeout = self.timeout.or(config.timeout);
+        if self.checks.is_empty() {
+            self.checks = config.checks.clone();
+        }
+        if self.data_ext.is_empty() {
+            self.data_ext = (config.data_ext.iter())
+                .map(|ext| {
+                    ext.parse()
+                        .map_err(|e| eyre!("invalid data_ext in configuration: {e}"))
+                })
+                .collect::<Result<_>>()?;
+        }
+        self.lua.apply_config(config);
+        Ok(())
+    }
+
     pub fn run(self) -> Result<()> {
+        self.run_with_stdin(io::stdin().lock())
+    }
+
+    /// Runs the command, reading the check for `--check-stdin` from `stdin`.
+    pub(crate) fn run_with_stdin(self, stdin: impl Read) -> Result<()> {
+        ensure!(
+            self.output.is_none()
+                || self.plain
+                || self.format != OutputFormat::Human
+                || self.list_checks
+                || self.list_data,
+            "--output needs --format github or --plain; use --report to write the human findings",
+        );
+        self.lua.report();
+        let data_extensions = DataExtensions::new(&self.data_ext)
+            .wrap_err("invalid --data-ext mapping")?
+            .with_overrides(&self.format_override);
+        if let Some(file) = &self.dump_value {
+            return (self.dump_value(file, &data_extensions))
+                .wrap_err_with(|| format!("dumping data file: {}", file.display()));
+        }
+        if self.watch {
+            return self.watch(&data_extensions);
+        }
+        let stdin_check = (self.check_stdin)
+            .then(|| read_stdin_check(stdin))
+            .transpose()?;
+        let stdin_check = stdin_check.as_ref();
+        if self.fix {
+            let mut fixes = Vec::new();
+            self.run_once(&data_extensions, stdin_check, Some(&mut fixes))?;
+            let applied = fix::apply(fixes)?;
+            info!(
+                fixes = applied,
+                "applied fixes; re-running checks to verify"
+            );
+        }
+        self.run_once(&data_extensions, stdin_check, None)
+    }
+
+    /// Runs the checks once, then re-runs them for every change to the inputs until killed.
+    fn watch(&self, data_extensions: &DataExtensions) -> Result<()> {
+        // How long to wait for more changes before re-running, so that e.g. an editor saving
+        // several files at once only causes a single re-run.
+        const DEBOUNCE: Duration = Duration::from_millis(200);
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).wrap_err("failed to create watcher")?;
+        // URLs can't be watched, so changes to them aren't noticed.
+        let inputs = (self.inputs()?.into_iter()).filter(|input| !is_url(input));
+        let mut paths = expand_globs(inputs.collect()).wrap_err("failed to expand input paths")?;
+        paths.extend(expand_globs(self.checks.clone()).wrap_err("failed to expand check paths")?);
+        for path in &paths {
+            watcher
+                .watch(path, RecursiveMode::Recursive)
+                .wrap_err_with(|| format!("failed to watch path: {}", path.display()))?;
+        }
+
+        loop {
+            if let Err(error) = self.run_once(data_extensions, None, None) {
+                error!("check run failed: {error:#}");
+            }
+            info!("watching for changes; press Ctrl-C to stop");
+
+            loop {
+                ensure!(!self.lua.is_cancelled(), CancelledError);
+                let event = match rx.recv_timeout(DEBOUNCE) {
+                    Ok(event) => event,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        return Err(eyre!("file watcher stopped unexpectedly"));
+                    }
+                };
+                match event {
+                    Ok(event) if is_relevant_change(&event, data_extensions) => break,
+                    Ok(_) => {}
+                    Err(error) => warn!(?error, "error while watching for changes"),
+                }
+            }
+            while rx.recv_timeout(DEBOUNCE).is_ok() {}
+            info!("---- change detected, re-running checks ----");
+        }
+    }
+
+    /// The input paths from the arguments and the `--input-list` file.
+    fn inputs(&self) -> Result<Vec<PathBuf>> {
+        let mut inputs = self.input.clone();
+        match &self.input_list {
+            Some(list) => {
+                let contents = std::fs::read_to_string(list)
+                    .wrap_err_with(|| format!("failed to read input list: {}", list.display()))?;
+                inputs.extend(
+                    contents
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .map(PathBuf::from),
+                );
+            }
+            None if inputs.is_empty() && self.data.is_empty() => inputs.push(PathBuf::from(".")),
+            None => {}
+        }
+        Ok(inputs)
+    }
+
+    /// How to split data files into documents.
+    fn parse_options(&self) -> ParseOptions<'_> {
+        ParseOptions {
+            json_array_as_documents: self.json_array_as_documents,
+            toml_delimiter: self.toml_multidoc.as_deref(),
+            normalize_numbers: self.normalize_numbers,
+            markdown_body: self.markdown_body,
+            markdown_without_front_matter: self.markdown_without_front_matter,
+        }
+    }
+
+    /// The documents given with `--data`, by their synthetic file names.
+    fn inline_data(&self) -> Vec<(PathBuf, InMemoryData)> {
+        let name = |i| match self.data.len() {
+            1 => PathBuf::from("<inline>"),
+            _ => PathBuf::from(format!("<inline #{i}>")),
+        };
+        (self.data.iter().enumerate())
+            .map(|(i, data)| {
+                let data = InMemoryData {
+                    contents: data.clone().into_bytes(),
+                    format: Some(self.data_format),
+                };
+                (name(i), data)
+            })
+            .collect()
+    }
+
+    /// Prints the documents of the data file as the checks would receive them, as a stream of
+    /// pretty JSON values.
+    ///
+    /// The data file is read like it would be for checking, so it may also be a URL or one of the
+    /// documents given with `--data`.
+    fn dump_value(&self, file: &Path, data_extensions: &DataExtensions) -> Result<()> {
+        use std::io::Write as _;
+
+        let mut in_memory = if is_url(file) {
+            remote::fetch_all(
+                &[file.to_path_buf()],
+                Duration::from_secs(self.timeout.unwrap_or(30)),
+                data_extensions,
+            )?
+        } else {
+            HashMap::new()
+        };
+        in_memory.extend(self.inline_data());
+        let options = CheckFileOptions {
+            lua: &self.lua,
+            memory_limit: self.memory_limit,
+            parallel_documents: false,
+            read_retries: self.read_retries,
+            context_input: None,
+            check_fn: &self.check_fn,
+            data_extensions,
+            continue_on_parse_error: false,
+            parse: self.parse_options(),
+            cache: None,
+            in_memory: &in_memory,
+            timings: None,
+        };
+        let contents = read_data_file(file, &options)?;
+        let format = data_format_of(file, &options)?;
+        let documents = parse_documents(&contents, format, options.parse)
+            .wrap_err("failed to parse data file")?;
+        let mut stdout = io::stdout().lock();
+        for document in documents {
+            serde_json::to_writer_pretty(&mut stdout, &document)
+                .wrap_err("failed to write document")?;
+            writeln!(stdout).wrap_err("failed to write document")?;
+        }
+        Ok(())
+    }
+
+    /// Prints the machine-readable output to stdout, or writes it to `--output`.
+    fn print_output(&self, output: &str) -> Result<()> {
+        match &self.output {
+            Some(path) => write_output(path, output),
+            None => {
+                print!("{output}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs the checks once.
+    ///
+    /// If `fixes` is given, the fixes suggested by the findings are collected into it, and
+    /// findings don't fail the run.
+    fn run_once(
+        &self,
+        data_extensions: &DataExtensions,
+        stdin_check: Option<&SourceCode>,
+        fixes: Option<&mut Vec<Fix>>,
+    ) -> Result<()> {
+        let started = Instant::now();
+        // The totals are recorded on the span once known, for log ingestion.
+        let span = info_span!(
+            "check_run",
+            files_checked = field::Empty,
+            errors = field::Empty,
+            warnings = field::Empty,
+            duration_ms = field::Empty,
+        );
+        let _entered = span.enter();
+        let cache = match &self.cache_dir {
+            Some(dir) if !self.no_cache && self.repeat.is_none() => {
+                let options = CacheOptions {
+                    check_fn: &self.check_fn,
+                    now: self.lua.now,
+                    today: (self.lua.now.is_none())
+                        .then(|| chrono::Utc::now().date_naive().to_string()),
+                    lua_path: &self.lua.lua_path,
+                    allow_unsafe: self.lua.allow_unsafe,
+                    seed: self.lua.seed,
+                    env: (self.lua.expose_env.iter())
+                        .map(|name| (name.as_str(), std::env::var(name).ok()))
+                        .collect(),
+                    prelude: (self.lua.prelude.as_ref())
+                        .map(|prelude| (prelude.path.as_path(), prelude.contents())),
+                    data_extensions,
+                    parse: self.parse_options(),
+                    context_json: self.context_json.as_ref(),
+                };
+                let salt = serde_json::to_string(&options)
+                    .wrap_err("failed to serialize the options for the cache")?;
+                Some(Cache::new(dir.clone(), &salt)?)
+            }
+            _ => None,
+        };
+        let fail_on = self.fail_on.unwrap_or_default();
+        let quiet = self.quiet;
+        // URLs may contain glob characters (e.g. `?`), so they're kept away from the expansion.
+        let (urls, input) = (self.inputs()?.into_iter()).partition::<Vec<_>, _>(|i| is_url(i));
+        let input = expand_globs(input).wrap_err("failed to expand input paths")?;
+        let mut in_memory = remote::fetch_all(
+            &urls,
+            Duration::from_secs(self.timeout.unwrap_or(30)),
+            data_extensions,
+        )?;
+        let inline = self.inline_data();
+        in_memory.extend(inline.iter().cloned());
+        let checks = expand_globs(self.checks.clone()).wrap_err("failed to expand check paths")?;
+        let timings = self.timings.then(Timings::default);
+        let options = CheckFileOptions {
+            lua: &self.lua,
+            memory_limit: self.memory_limit,
+            parallel_documents: self.parallel_documents,
+            read_retries: self.read_retries,
+            context_input: self.context_json.as_ref(),
+            check_fn: &self.check_fn,
+            data_extensions,
+            continue_on_parse_error: self.continue_on_parse_error,
+            parse: self.parse_options(),
+            cache: cache.as_ref(),
+            in_memory: &in_memory,
+            timings: timings.as_ref(),
+        };
+        let discover_checks = self.only.is_empty() && stdin_check.is_none();
         let FileSearchResult {
             mut check_files,
-            test_files: _,
-            data_files,
+            mut test_files,
+            mut data_files,
         } = FileSearcher::default()
-            .include_dotfiles(self.dotfiles)
-            .include_dotdirs(self.dotfiles)
-            .include_check_files(true)
+            .data_extensions(data_extensions.clone())
+            .include_dotfiles(self.dotfiles.unwrap_or(false))
+            .include_dotdirs(self.dotdirs.unwrap_or(true))
+            .exclude(self.exclude.clone())
+            .include_check_files(discover_checks)
+            .include_test_files(discover_checks && self.no_ignore_tests)
             .include_data_files(true)
-            .search(self.input.into_par_iter())
+            .search(input.clone().into_par_iter())
             .wrap_err("failed to search input paths for relevant files")?;
-        {
+        if let Some(base) = &self.diff_base {
+            let within = input.first().map_or(Path::new("."), PathBuf::as_path);
+            let changed = changed_files(base, within)?;
+            data_files.retain(|path| {
+                std::fs::canonicalize(path).is_ok_and(|path| changed.contains(&path))
+            });
+            debug!(
+                base,
+                count = data_files.len(),
+                "restricted data files to changed files"
+            );
+        }
+        data_files.extend(urls);
+        data_files.extend(inline.into_iter().map(|(name, _)| name));
+        if self.list_data {
+            let mut by_format = BTreeMap::<_, Vec<_>>::new();
+            for path in &data_files {
+                let format = match in_memory.get(path) {
+                    Some(data) => data.format,
+                    None => data_extensions.format_of(path),
+                };
+                by_format.entry(format).or_default().push(path);
+            }
+            let mut listing = String::new();
+            for (format, mut paths) in by_format {
+                paths.sort_unstable();
+                match format {
+                    Some(format) => writeln!(listing, "{format}:")?,
+                    None => writeln!(listing, "unknown:")?,
+                }
+                for path in paths {
+                    writeln!(listing, "  {}", path.display())?;
+                }
+            }
+            return self.print_output(&listing);
+        }
+        if discover_checks {
             // Additional check files
             let FileSearchResult {
                 check_files: mut extra_check_files,
-                test_files: _,
+                test_files: mut extra_test_files,
                 data_files: _,
             } = FileSearcher::default()
-                .include_dotfiles(self.dotfiles)
-                .include_dotdirs(self.dotfiles)
+                .include_dotfiles(self.dotfiles.unwrap_or(false))
+                .include_dotdirs(self.dotdirs.unwrap_or(true))
+                .exclude(self.exclude.clone())
                 .include_check_files(true)
-                .search(self.checks.into_par_iter())
+                .include_test_files(self.no_ignore_tests)
+                .search(checks.into_par_iter())
                 .wrap_err("failed to search additional check paths for relevant files")?;
             check_files.append(&mut extra_check_files);
+            test_files.append(&mut extra_test_files);
+        } else {
+            check_files = self.only.clone();
+        }
+        // Test files are only found when we want to treat them as check files.
+        check_files.append(&mut test_files);
+        if !self.select.is_empty() {
+            check_files = select_check_files(check_files, &self.select);
         }
 
-        let check_files = check_files
+        let unused = Mutex::new(Vec::new());
+        let broken = Mutex::new(Vec::new());
+        let mut check_files = check_files
             .into_par_iter()
             .map(|p| {
-                SourceCode::read(&p)
+                SourceCode::read_with_retries(&p, self.read_retries)
                     .wrap_err_with(|| format!("reading check file: {}", p.display()))
             })
             .filter_map(|src| {
-                match src.and_then(|src| src.has_check_function().map(|b| b.then_some(src))) {
-                    Ok(Some(src)) => Some(Ok(src)),
-                    Ok(None) => None,
+                let mut src = match src {
+                    Ok(src) => src,
+                    Err(e) => return Some(Err(e)),
+                };
+                match src.discover_check_functions(options.lua, options.check_fn) {
+                    Ok(true) => Some(Ok(src)),
+                    // Discovered files without a check function are assumed to be libraries.
+                    Ok(false) if discover_checks => {
+                        if self.warn_unused_checks && !is_test_file(&src.path) {
+                            (unused.lock().unwrap_or_else(PoisonError::into_inner)).push(src.path);
+                        }
+                        None
+                    }
+                    Ok(false) => Some(Err(eyre!(
+                        "check file does not define a '{}' function: {}",
+                        options.check_fn,
+                        src.path.display()
+                    ))),
+                    Err(e) if self.skip_broken_checks && e.is::<LoadError>() => {
+                        (broken.lock().unwrap_or_else(PoisonError::into_inner)).push((src.path, e));
+                        None
+                    }
                     Err(e) => Some(Err(e)),
                 }
             })
             .collect::<Result<Vec<_>>>()?;
+        let mut broken = broken.into_inner().unwrap_or_else(PoisonError::into_inner);
+        broken.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        for (path, error) in broken {
+            warn!(
+                path = %path.display(),
+                "check file failed to load; skipping it: {error:#}",
+            );
+        }
+        let mut unused = unused.into_inner().unwrap_or_else(PoisonError::into_inner);
+        unused.sort_unstable();
+        for path in unused {
+            warn!(
+                path = %path.display(),
+                check_fn = options.check_fn,
+                "check file does not define a check function; skipping it",
+            );
+        }
+        if let Some(check) = stdin_check {
+            let mut check = check.clone();
+            ensure!(
+                check.discover_check_functions(options.lua, options.check_fn)?,
+                "check from stdin does not define a '{}' function",
+                options.check_fn
+            );
+            check_files.push(check);
+        }
+        // Run the checks in a fixed order, regardless of the order we found them in.
+        check_files.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+
+        if self.list_checks {
+            let mut listing = String::new();
+            for check in &check_files {
+                writeln!(listing, "{}", check.path.display())?;
+            }
+            return self.print_output(&listing);
+        }
 
         ensure!(!check_files.is_empty(), "no check files found to run");
+        if data_files.is_empty() && self.diff_base.is_some() {
+            if !quiet {
+                info!("no changed data files to check");
+            }
+            return Ok(());
+        }
         ensure!(!data_files.is_empty(), "no data files found to check");
         // We now have all the Lua files (i.e. checks) and all the data files we want to run on.
 
-        #[derive(Debug, Clone)]
-        struct EvalResult<'a> {
-            data_file: PathBuf,
-            /// The errors in a tuple of `(check_file, errors)`.
-            /// If no errors are found for a check, it won't be included.
-            errors: Vec<(&'a SourceCode, Vec<CheckError>)>,
+        let progress = if self.progress && io::stderr().is_terminal() {
+            ProgressBar::new(data_files.len() as u64).with_style(
+                ProgressStyle::with_template("{bar:40} {pos}/{len} files checked")
+                    .wrap_err("invalid progress bar template")?,
+            )
+        } else {
+            ProgressBar::hidden()
+        };
+        let mut baseline = match &self.baseline {
+            Some(path) if !self.write_baseline => Some(Baseline::load(path)?),
+            _ => None,
+        };
+        let total_files = data_files.len();
+        let bail = AtomicBool::new(false);
+        let evaluate = |options: &CheckFileOptions, progress: &ProgressBar| {
+            if self.aggregate {
+                return check_aggregate(&data_files, &check_files, options, progress)
+                    .map(|results| results.into_iter().map(Some).collect());
+            }
+            data_files
+                .par_iter()
+                .map(|file| {
+                    if bail.load(Ordering::Relaxed) || self.lua.is_cancelled() {
+                        return Ok(None);
+                    }
+                    let display = file.display().to_string();
+                    let started = Instant::now();
+                    let timed_file = options.timings.map(|_| file.clone());
+                    let result = check_file(file.clone(), &check_files, options)
+                        .wrap_err_with(|| format!("checking data file: {display}"));
+                    if let Some((timings, file)) = options.timings.zip(timed_file) {
+                        timings.record_data_file(file, started.elapsed());
+                    }
+                    progress.inc(1);
+                    // The checks of a data file aborted by the cancellation didn't finish.
+                    if result.is_err() && self.lua.is_cancelled() {
+                        return Ok(None);
+                    }
+                    let result = result?;
+                    if self.fail_fast && result.fails(fail_on, baseline.as_ref()) {
+                        bail.store(true, Ordering::Relaxed);
+                    }
+                    Ok(Some(result))
+                })
+                .collect::<Result<Vec<Option<EvalResult>>>>()
+        };
+        let evaluation_started = Instant::now();
+        let results = evaluate(&options, &progress);
+        let first_run = evaluation_started.elapsed();
+        progress.finish_and_clear();
+        // Whatever the checks failed with, they were most likely aborted by the cancellation.
+        let cancelled = self.lua.is_cancelled();
+        ensure!(
+            !cancelled || (self.flush_on_interrupt && fixes.is_none() && results.is_ok()),
+            CancelledError
+        );
+        let mut results = results?.into_iter().flatten().collect::<Vec<_>>();
+        if let Some(repeat) = self.repeat.filter(|_| !cancelled) {
+            // The later runs are only timed, so they don't add to the timings of the first.
+            let options = CheckFileOptions {
+                timings: None,
+                ..options.clone()
+            };
+            let mut runs = vec![first_run];
+            for _ in 1..repeat {
+                let started = Instant::now();
+                evaluate(&options, &ProgressBar::hidden())?;
+                ensure!(!self.lua.is_cancelled(), CancelledError);
+                runs.push(started.elapsed());
+            }
+            runs.sort_unstable();
+            let format = |d: Duration| format!("{:.3}s", d.as_secs_f64());
+            let (min, median, max) = (runs[0], runs[runs.len() / 2], runs[runs.len() - 1]);
+            info!(
+                runs = runs.len(),
+                min_ms = min.as_millis() as u64,
+                median_ms = median.as_millis() as u64,
+                max_ms = max.as_millis() as u64,
+                "ran {}: min {}, median {}, max {}",
+                plural(runs.len(), "time"),
+                format(min),
+                format(median),
+                format(max),
+            );
         }
-        let mut results: Vec<EvalResult> = data_files
-            .into_par_iter()
-            .map(|file| {
-                let f2 = file.clone();
-                Ok(EvalResult {
-                    errors: check_file(file, &check_files)
-                        .wrap_err_with(|| format!("checking data file: {}", f2.display()))?,
-                    data_file: f2,
+        if bail.into_inner() {
+            info!(
+                skipped = total_files - results.len(),
+                "stopped checking at the first failing data file",
+            );
+        }
+        // The same data file may be given more than once, so break ties by the findings to keep
+        // the output deterministic.
+        results.sort_by_cached_key(|r| {
+            let findings = r
+                .errors
+                .iter()
+                .map(|(check, errs)| {
+                    let errs = errs.iter().map(ToString::to_string).collect::<Vec<_>>();
+                    (check.path.clone(), errs)
                 })
-            })
-            .collect::<Result<Vec<EvalResult>>>()?;
-        results.sort_unstable_by_key(|e| e.data_file.clone());
+                .collect::<Vec<_>>();
+            (r.data_file.clone(), findings)
+        });
+        // Collecting fixes is silent, as the run verifying them reports whatever remains.
+        if let Some(fixes) = fixes {
+            // Data that isn't on disk can't be fixed.
+            for res in results
+                .iter()
+                .filter(|r| !in_memory.contains_key(&r.data_file))
+            {
+                for (check, errs) in &res.errors {
+                    for e in errs {
+                        let entry = BaselineEntry::new(&res.data_file, &check.path, e);
+                        if baseline.as_mut().is_some_and(|b| b.suppresses(&entry)) {
+                            continue;
+                        }
+                        fixes.extend(Fix::from_finding(&res.data_file, e));
+                    }
+                }
+            }
+            return Ok(());
+        }
+        let mut new_baseline = Baseline::default();
+        // In aggregate mode, the results aren't per data file.
+        let files_checked = if self.aggregate {
+            total_files
+        } else {
+            results.len()
+        };
         let mut found_error = false;
+        let mut parse_failures = 0;
+        let mut unchecked = 0;
+        let mut error_count = 0;
+        let mut warning_count = 0;
+        // How many findings were reported, and how many weren't due to `--max-findings`.
+        let mut shown = 0;
+        let mut suppressed = 0;
+        // The human-readable report for `--report`, mirroring what we log.
+        let mut report = String::new();
+        // The output of `--format github` or `--plain`, for stdout or `--output`.
+        let mut output = String::new();
         for res in results {
             let path = res.data_file.display();
-            for (check, errs) in res.errors {
-                let (errors, warnings) = errs
-                    .iter()
-                    .partition::<Vec<_>, _>(|e| e.severity == CheckSeverity::Error);
-                found_error |= !errors.is_empty();
+            // Markdown files without front matter have no documents, so there's nothing to check.
+            let markdown =
+                data_format_of(&res.data_file, &options).is_ok_and(|f| f == DataFormat::Markdown);
+            if self.strict && res.parse_error.is_none() && res.invocations == 0 && !markdown {
+                unchecked += 1;
+                error!(%path, "no check was run against data file");
+                if self.format == OutputFormat::Github {
+                    writeln!(
+                        output,
+                        "::error file={}::{}",
+                        github_escape(&path.to_string(), true),
+                        github_escape("no check was run against data file", false),
+                    )?;
+                }
+                if self.plain {
+                    let message = "no check was run against data file";
+                    writeln!(
+                        output,
+                        "{}",
+                        plain_line(CheckSeverity::Error, &res.data_file, None, message)
+                    )?;
+                }
+                writeln!(report, "{path}: no check was run against data file")?;
+            }
+            if let Some(parse_error) = &res.parse_error {
+                parse_failures += 1;
+                error_count += 1;
+                error!(%path, error = %parse_error, "failed to parse data file");
+                if self.format == OutputFormat::Github {
+                    writeln!(
+                        output,
+                        "{}",
+                        github_annotation(&res.data_file, None, parse_error)
+                    )?;
+                }
+                if self.plain {
+                    writeln!(
+                        output,
+                        "{}",
+                        plain_line(
+                            parse_error.severity,
+                            &res.data_file,
+                            None,
+                            &parse_error.error
+                        )
+                    )?;
+                }
+                writeln!(report, "{path}: failed to parse data file: {parse_error}")?;
+            }
+            for (check, mut errs) in res.errors {
+                if self.write_baseline {
+                    for e in &errs {
+                        new_baseline.insert(BaselineEntry::new(&res.data_file, &check.path, e));
+                    }
+                }
+                if let Some(baseline) = &mut baseline {
+                    errs.retain(|e| {
+                        !baseline.suppresses(&BaselineEntry::new(&res.data_file, &check.path, e))
+                    });
+                }
+                found_error |= errs.iter().any(|e| fail_on.fails_on(e.severity));
+                let warnings = (errs.iter())
+                    .filter(|e| e.severity == CheckSeverity::Warning)
+                    .count();
+                error_count += errs.len() - warnings;
+                warning_count += warnings;
+                // The findings beyond the cap are still counted, but not reported.
+                let budget = self.max_findings.map_or(usize::MAX, |max| max - shown);
+                if errs.len() > budget {
+                    suppressed += errs.len() - budget;
+                    errs.truncate(budget);
+                }
+                shown += errs.len();
+                let of_severity = |severity| {
+                    errs.iter()
+                        .filter(|e| e.severity == severity)
+                        .map(|e| e.to_string())
+                        .collect::<Vec<_>>()
+                };
+                let fatal = of_severity(CheckSeverity::Fatal);
+                let errors = of_severity(CheckSeverity::Error);
+                let warnings = of_severity(CheckSeverity::Warning);
+                if self.format == OutputFormat::Github {
+                    for e in &errs {
+                        writeln!(
+                            output,
+                            "{}",
+                            github_annotation(&res.data_file, Some(&check.path), e)
+                        )?;
+                    }
+                }
+                if self.plain {
+                    for e in &errs {
+                        let line =
+                            plain_line(e.severity, &res.data_file, Some(&check.path), &e.error);
+                        writeln!(output, "{line}")?;
+                    }
+                }
                 let check = check.path.display();
+                for finding in fatal.iter().chain(&errors).chain(&warnings) {
+                    writeln!(report, "{path}: {check}: {finding}")?;
+                }
+                if !fatal.is_empty() {
+                    error!(
+                        %path,
+                        count = fatal.len(),
+                        ?fatal,
+                        %check,
+                        "fatal findings found by check; skipped remaining checks",
+                    );
+                }
                 if !errors.is_empty() {
                     error!(%path, count = errors.len(), ?errors, %check, "errors found by check");
                 }
@@ -115,99 +1175,1276 @@ impl Check {
                 }
             }
         }
-        ensure!(!found_error, "one or more errors were found during checks");
-        info!("no errors found");
+        if suppressed > 0 {
+            let note = format!("... and {} suppressed", plural(suppressed, "more finding"));
+            warn!(suppressed, "{note}");
+            writeln!(report, "{note}")?;
+        }
+        let duration_ms = started.elapsed().as_millis() as u64;
+        span.record("files_checked", files_checked);
+        span.record("errors", error_count);
+        span.record("warnings", warning_count);
+        span.record("duration_ms", duration_ms);
+        if !quiet || error_count + warning_count > 0 {
+            writeln!(
+                report,
+                "checked {} with {}: {}, {}",
+                plural(files_checked, "file"),
+                plural(check_files.len(), "check"),
+                plural(error_count, "error"),
+                plural(warning_count, "warning"),
+            )?;
+        }
+        // Unlike the summary line above, the event is logged even with `--quiet`, so log ingestion
+        // always gets the totals.
+        info!(
+            files_checked,
+            checks = check_files.len(),
+            errors = error_count,
+            warnings = warning_count,
+            duration_ms,
+            "checked {} with {}: {}, {}",
+            plural(files_checked, "file"),
+            plural(check_files.len(), "check"),
+            plural(error_count, "error"),
+            plural(warning_count, "warning"),
+        );
+        self.print_output(&output)?;
+        if let Some(timings) = options.timings {
+            timings.log();
+        }
+        if let Some(baseline) = baseline.as_ref().filter(|_| !cancelled) {
+            for entry in baseline.stale() {
+                warn!(
+                    path = %entry.data_file.display(),
+                    check = %entry.check_file.display(),
+                    id = entry.id,
+                    message = entry.message,
+                    "stale baseline entry no longer matches any finding",
+                );
+                writeln!(
+                    report,
+                    "{}: {}: stale baseline entry no longer matches any finding: {}",
+                    entry.data_file.display(),
+                    entry.check_file.display(),
+                    entry.message,
+                )?;
+            }
+        }
+        if let Some(path) = &self.report {
+            std::fs::write(path, &report)
+                .wrap_err_with(|| format!("failed to write report: {}", path.display()))?;
+        }
+        if let Some(path) = &self.summary_json {
+            let summary = RunSummary {
+                errors: error_count,
+                warnings: warning_count,
+                files_checked,
+                checks_run: check_files.len(),
+                duration_ms,
+            };
+            let json = serde_json::to_string(&summary).wrap_err("failed to serialize summary")?;
+            std::fs::write(path, json + "\n")
+                .wrap_err_with(|| format!("failed to write summary: {}", path.display()))?;
+        }
+        if cancelled {
+            warn!("interrupted; only the data files checked so far were reported");
+            return Err(CancelledError.into());
+        }
+        if let Some(path) = self.baseline.as_ref().filter(|_| self.write_baseline) {
+            new_baseline.save(path)?;
+            info!(path = %path.display(), "wrote baseline file");
+            return Ok(());
+        }
+        ensure!(
+            unchecked == 0,
+            FindingsError(format!(
+                "{} not checked by any check",
+                plural(unchecked, "data file")
+            ))
+        );
+        ensure!(
+            parse_failures == 0,
+            FindingsError(format!(
+                "{} failed to parse",
+                plural(parse_failures, "data file")
+            ))
+        );
+        ensure!(
+            !found_error,
+            FindingsError(
+                "one or more findings at or above the fail-on threshold were found during checks"
+                    .to_string()
+            )
+        );
+        if !quiet && error_count + warning_count == 0 {
+            info!("no errors found");
+        }
         Ok(())
     }
 }
 
-fn check_file(
-    file: impl AsRef<Path>,
-    checks: &[SourceCode],
-) -> Result<Vec<(&SourceCode, Vec<CheckError>)>> {
-    let file = file.as_ref();
-    let lua = Lua::new();
-    let documents = parse_data(&lua, file).wrap_err("failed to parse data file")?;
-
-    fn perform_check(
-        lua: Lua,
-        doc_file: &Path,
-        documents: &[mlua::Value],
-        check: &SourceCode,
-    ) -> Result<Vec<CheckError>> {
-        check.load_into(&lua).wrap_err_with(|| {
-            format!(
-                "failed to load check source code from file: {}",
-                check.path.display()
-            )
-        })?;
+/// Formats a finding for `--plain`, as tab-separated severity, data file, check file, and message.
+pub(crate) fn plain_line(
+    severity: CheckSeverity,
+    data_file: &Path,
+    check: Option<&Path>,
+    message: &str,
+) -> String {
+    let escape = |value: &str| {
+        value
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    };
+    let check = check.map_or_else(|| "-".to_string(), |c| escape(&c.display().to_string()));
+    format!(
+        "{}\t{}\t{check}\t{}",
+        severity.as_str(),
+        escape(&data_file.display().to_string()),
+        escape(message),
+    )
+}
 
-        let context = lua
-            .create_table_from([
-                ("check_file", check.path.to_string_lossy()),
-                ("document_file", doc_file.to_string_lossy()),
-            ])
-            .map_err(|e| eyre!("failed to create context table: {e}"))?;
-        let context = mlua::Value::Table(context);
+/// Formats a finding as a GitHub Actions workflow command, e.g.
+/// `::error file=data.json,line=3,title=check.lua::message`.
+///
+/// Findings of `--aggregate` aren't of any one file, so they're annotated without a location.
+pub(crate) fn github_annotation(
+    data_file: &Path,
+    check: Option<&Path>,
+    finding: &CheckError,
+) -> String {
+    let command = match finding.severity {
+        CheckSeverity::Fatal | CheckSeverity::Error => "error",
+        CheckSeverity::Warning => "warning",
+    };
+    let mut properties = Vec::new();
+    let location = finding.location.as_ref();
+    if data_file != Path::new(AGGREGATE_FILE) {
+        properties.push(("file", data_file.display().to_string()));
+        if let Some(line) = location.and_then(|l| l.line) {
+            properties.push(("line", line.to_string()));
+        }
+        if let Some(column) = location.and_then(|l| l.column) {
+            properties.push(("col", column.to_string()));
+        }
+    }
+    if let Some(check) = check {
+        properties.push(("title", check.display().to_string()));
+    }
+    let properties = (properties.iter())
+        .map(|(key, value)| format!("{key}={}", github_escape(value, true)))
+        .collect::<Vec<_>>()
+        .join(",");
 
-        let mut errors = Vec::new();
-        for doc in documents {
-            let res = check.call_check_function(&lua, doc, &context)?;
-            errors.extend(res);
+    let mut message = match &finding.id {
+        Some(id) => format!("[{id}] {}", finding.error),
+        None => finding.error.clone(),
+    };
+    let mut details = Vec::with_capacity(2);
+    if let Some(index) = finding.document_index {
+        details.push(format!("document #{index}"));
+    }
+    if let Some(path) = location.and_then(|l| l.path.as_ref()) {
+        details.push(format!("at {path}"));
+    }
+    if let Some(function) = &finding.function {
+        details.push(format!("from {function}"));
+    }
+    if let Some(fix) = &finding.fix {
+        details.push(format!("suggested fix: {fix}"));
+    }
+    if !details.is_empty() {
+        write!(message, " ({})", details.join(", ")).expect("writing to a String cannot fail");
+    }
+    let separator = if properties.is_empty() { "" } else { " " };
+    format!(
+        "::{command}{separator}{properties}::{}",
+        github_escape(&message, false)
+    )
+}
+
+/// Escapes a value for a GitHub Actions workflow command. Property values need more escaping than
+/// the message.
+fn github_escape(value: &str, property: bool) -> String {
+    let value = value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    if property {
+        value.replace(':', "%3A").replace(',', "%2C")
+    } else {
+        value
+    }
+}
+
+/// The canonical paths of the files that differ from the given git ref, or that git doesn't track
+/// and doesn't ignore, in the repository containing the given path.
+pub(crate) fn changed_files(base: &str, within: &Path) -> Result<HashSet<PathBuf>> {
+    let dir = match within {
+        dir if dir.is_dir() => dir,
+        file => (file.parent())
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new(".")),
+    };
+    let git = |args: &[&str]| -> Result<Vec<u8>> {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .wrap_err("failed to run git")?;
+        ensure!(
+            output.status.success(),
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        Ok(output.stdout)
+    };
+    let root = git(&["rev-parse", "--show-toplevel"])
+        .wrap_err_with(|| format!("not in a git repository: {}", dir.display()))?;
+    let root = String::from_utf8(root).wrap_err("non UTF-8 git repository path")?;
+    let root =
+        std::fs::canonicalize(root.trim_end()).wrap_err("failed to resolve git repository path")?;
+    let mut names = git(&["diff", "--name-only", "-z", base, "--"])
+        .wrap_err_with(|| format!("failed to diff against git ref: {base}"))?;
+    // New files are changes too, until they're committed. Ignored files are still left out.
+    let untracked = [
+        "ls-files",
+        "-z",
+        "--others",
+        "--exclude-standard",
+        "--full-name",
+        "--",
+        ":/",
+    ];
+    names.push(0);
+    names.extend(git(&untracked).wrap_err("failed to list untracked files")?);
+    names
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let name = std::str::from_utf8(name).wrap_err("non UTF-8 path in git diff")?;
+            Ok(root.join(name))
+        })
+        .collect()
+}
+
+/// Reads the check given on stdin with `--check-stdin`, as if it were a file in the current
+/// directory.
+fn read_stdin_check(mut stdin: impl Read) -> Result<SourceCode> {
+    let mut contents = String::new();
+    stdin
+        .read_to_string(&mut contents)
+        .wrap_err("failed to read check from stdin")?;
+    let dir = std::env::current_dir().wrap_err("failed to get current directory")?;
+    Ok(SourceCode::new(dir.join("<stdin>"), contents))
+}
+
+/// Writes the machine-readable output to the file, creating its parent directories if needed.
+fn write_output(path: &Path, output: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("failed to create output directory: {}", parent.display()))?;
+    }
+    std::fs::write(path, output)
+        .wrap_err_with(|| format!("failed to write output: {}", path.display()))
+}
+
+/// Parses the value of `--context-json`, reading it from a file if prefixed with `@`.
+fn parse_context_json(s: &str) -> Result<serde_json::Value, String> {
+    match s.strip_prefix('@') {
+        Some(path) => {
+            let contents = std::fs::read(path)
+                .map_err(|e| format!("failed to read context file {path}: {e}"))?;
+            serde_json::from_slice(&contents)
+                .map_err(|e| format!("invalid JSON in context file {path}: {e}"))
         }
+        None => serde_json::from_str(s).map_err(|e| format!("invalid JSON: {e}")),
+    }
+}
 
-        Ok(errors)
+/// Parses the `KEY=VALUE` lines of a `.env` file into an object.
+///
+/// Blank lines and lines starting with `#` are skipped, and an `export ` prefix is stripped. Values
+/// may be quoted with single or double quotes; double-quoted values may use the escapes `\n`, `\"`,
+/// and `\\`. Unquoted values end at a ` #` comment.
+fn parse_env(contents: &str) -> Result<serde_json::Value> {
+    let mut values = serde_json::Map::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| eyre!("failed to parse env file: line {} has no '='", index + 1))?;
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('"') {
+            let quoted = quoted.rsplit_once('"').map(|(q, _)| q).ok_or_else(|| {
+                eyre!(
+                    "failed to parse env file: unterminated quote on line {}",
+                    index + 1
+                )
+            })?;
+            let mut unescaped = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars().peekable();
+            while let Some(c) = chars.next() {
+                let escaped = match (c, chars.peek()) {
+                    ('\\', Some('n')) => '\n',
+                    ('\\', Some(&e @ ('"' | '\\'))) => e,
+                    _ => {
+                        unescaped.push(c);
+                        continue;
+                    }
+                };
+                chars.next();
+                unescaped.push(escaped);
+            }
+            unescaped
+        } else if let Some(quoted) = value.strip_prefix('\'') {
+            let (quoted, _) = quoted.rsplit_once('\'').ok_or_else(|| {
+                eyre!(
+                    "failed to parse env file: unterminated quote on line {}",
+                    index + 1
+                )
+            })?;
+            quoted.to_string()
+        } else {
+            let value = value.split_once(" #").map_or(value, |(v, _)| v);
+            value.trim_end().to_string()
+        };
+        values.insert(key.trim().to_string(), serde_json::Value::String(value));
     }
+    Ok(serde_json::Value::Object(values))
+}
 
-    let mut results = Vec::new();
+/// Keeps only the check files whose file name matches any of the globs.
+///
+/// Globs that don't match any check file are warned about, as they are likely typos.
+fn select_check_files(check_files: Vec<PathBuf>, globs: &[Glob]) -> Vec<PathBuf> {
+    let matchers = globs.iter().map(Glob::compile_matcher).collect::<Vec<_>>();
+    let mut used = vec![false; matchers.len()];
+    let check_files = check_files
+        .into_iter()
+        .filter(|path| {
+            let Some(name) = path.file_name() else {
+                return false;
+            };
+            let mut selected = false;
+            for (matcher, used) in matchers.iter().zip(&mut used) {
+                if matcher.is_match(name) {
+                    *used = true;
+                    selected = true;
+                }
+            }
+            selected
+        })
+        .collect();
+    for (glob, _) in globs.iter().zip(used).filter(|(_, used)| !used) {
+        warn!(
+            glob = glob.glob(),
+            "--select glob did not match any check file"
+        );
+    }
+    check_files
+}
+
+/// Whether a file system event may change the outcome of a check run.
+pub(crate) fn is_relevant_change(event: &notify::Event, data_extensions: &DataExtensions) -> bool {
+    !event.kind.is_access()
+        && event.paths.iter().any(|p| {
+            p.extension().is_some_and(|e| e == "lua") || data_extensions.format_of(p).is_some()
+        })
+}
+
+/// Formats a count with a noun, pluralising the noun if needed, e.g. `3 errors`.
+pub(crate) fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{count} {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
+
+/// Options for how to run the checks against a single data file.
+#[derive(Debug, Clone)]
+struct CheckFileOptions<'a> {
+    /// The options for creating Lua states.
+    lua: &'a LuaOptions,
+    /// The Lua memory limit in bytes, or 0 for unlimited.
+    memory_limit: usize,
+    /// Whether the documents of a data file are checked in parallel, each in its own Lua state.
+    parallel_documents: bool,
+    /// How many more times to try reading a data file after a transient IO error.
+    read_retries: u32,
+    /// The value given with `--context-json`, passed to the checks as `context.input`.
+    context_input: Option<&'a serde_json::Value>,
+    /// The name of the check function to call.
+    check_fn: &'a str,
+    /// The extensions to detect data formats by.
+    data_extensions: &'a DataExtensions,
+    /// Whether a data file that fails to parse is reported as a finding, rather than failing the
+    /// run immediately.
+    continue_on_parse_error: bool,
+    /// How to split data files into documents.
+    parse: ParseOptions<'a>,
+    /// The cache of findings from earlier runs, if enabled.
+    cache: Option<&'a Cache>,
+    /// The data files that aren't read from disk, by their URL or synthetic name.
+    in_memory: &'a HashMap<PathBuf, InMemoryData>,
+    /// Where to record how long the checks take, if `--timings` is given.
+    timings: Option<&'a Timings>,
+}
+
+/// How long the checks and data files took, as recorded from all threads for `--timings`.
+#[derive(Debug, Default)]
+struct Timings {
+    /// The total time and number of runs of each check file.
+    checks: Mutex<HashMap<PathBuf, (Duration, usize)>>,
+    /// The time taken to run all checks against each data file.
+    data_files: Mutex<Vec<(PathBuf, Duration)>>,
+}
+
+impl Timings {
+    /// How many of the slowest data files are printed.
+    const SLOWEST_DATA_FILES: usize = 10;
+
+    fn record_check(&self, check: &Path, duration: Duration) {
+        let mut checks = self.checks.lock().unwrap_or_else(PoisonError::into_inner);
+        let (total, runs) = checks.entry(check.to_path_buf()).or_default();
+        *total += duration;
+        *runs += 1;
+    }
+
+    fn record_data_file(&self, data_file: PathBuf, duration: Duration) {
+        (self
+            .data_files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner))
+        .push((data_file, duration));
+    }
+
+    /// Logs the checks, slowest first, then the slowest data files.
+    ///
+    /// This goes to the log rather than stdout, which holds the findings for `--format github`
+    /// and `--plain`.
+    fn log(&self) {
+        let format = |d: Duration| format!("{:.3}s", d.as_secs_f64());
+        let mut checks = (self.checks.lock().unwrap_or_else(PoisonError::into_inner))
+            .iter()
+            .map(|(path, &(total, runs))| (path.clone(), total, runs))
+            .collect::<Vec<_>>();
+        checks.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        info!("{:>10}  {:>6}  check", "time", "runs");
+        for (path, total, runs) in checks {
+            info!("{:>10}  {runs:>6}  {}", format(total), path.display());
+        }
+
+        let mut data_files = self
+            .data_files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        data_files.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        info!("{:>10}  data file", "time");
+        for (path, duration) in data_files.iter().take(Self::SLOWEST_DATA_FILES) {
+            info!("{:>10}  {}", format(*duration), path.display());
+        }
+    }
+}
+
+/// The findings of all checks against a single data file.
+#[derive(Debug, Clone)]
+struct EvalResult<'a> {
+    data_file: PathBuf,
+    /// The errors in a tuple of `(check_file, errors)`.
+    /// If no errors are found for a check, it won't be included.
+    errors: Vec<(&'a SourceCode, Vec<CheckError>)>,
+    /// The reason the data file could not be parsed, if it couldn't be and we continue past parse
+    /// errors. No checks are run in that case.
+    parse_error: Option<CheckError>,
+    /// How many times any check function was called for this data file.
+    invocations: usize,
+}
+
+impl<'a> EvalResult<'a> {
+    /// Adds the outcome of a check, returning whether it had a fatal finding.
+    fn add(&mut self, check: &'a SourceCode, run: CheckRun) -> bool {
+        self.invocations += run.invocations;
+        let fatal = run
+            .findings
+            .iter()
+            .any(|f| f.severity == CheckSeverity::Fatal);
+        if !run.findings.is_empty() {
+            self.errors.push((check, run.findings));
+        }
+        fatal
+    }
+
+    /// Whether any finding not in the baseline would fail the run.
+    fn fails(&self, fail_on: FailOn, baseline: Option<&Baseline>) -> bool {
+        self.parse_error.is_some()
+            || self.errors.iter().any(|(check, errs)| {
+                errs.iter().any(|e| {
+                    fail_on.fails_on(e.severity)
+                        && !baseline.is_some_and(|b| {
+                            b.contains(&BaselineEntry::new(&self.data_file, &check.path, e))
+                        })
+                })
+            })
+    }
+}
+
+/// The outcome of running a single check file against a data file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CheckRun {
+    /// How many times the check function was called, i.e. once per document.
+    invocations: usize,
+    findings: Vec<CheckError>,
+}
+
+/// The totals of a check run, as written by `--summary-json`.
+#[derive(Debug, Serialize)]
+struct RunSummary {
+    errors: usize,
+    warnings: usize,
+    files_checked: usize,
+    /// How many check files were run against the data files.
+    checks_run: usize,
+    duration_ms: u64,
+}
+
+/// The rule ID of the findings for data files that fail to parse.
+const PARSE_ERROR_ID: &str = "parse";
+
+fn check_file<'a>(
+    data_file: PathBuf,
+    checks: &'a [SourceCode],
+    options: &CheckFileOptions,
+) -> Result<EvalResult<'a>> {
+    let file = data_file.as_path();
+    debug!(path = %file.display(), "checking data file");
+    let contents = read_data_file(file, options);
+
+    let cache_keys = match (&contents, options.cache) {
+        (Ok(contents), Some(cache)) => checks
+            .iter()
+            .map(|check| Some(cache.key(file, contents, check)))
+            .collect(),
+        _ => vec![None; checks.len()],
+    };
+    let mut cached = cache_keys
+        .iter()
+        .map(|key| {
+            key.as_deref()
+                .zip(options.cache)
+                .and_then(|(k, c)| c.get(k))
+        })
+        .collect::<Vec<_>>();
+    if cached.iter().all(Option::is_some) {
+        debug!(path = %file.display(), "using cached findings for all checks");
+        let mut result = EvalResult {
+            data_file,
+            errors: Vec::new(),
+            parse_error: None,
+            invocations: 0,
+        };
+        for (check, run) in checks.iter().zip(cached.into_iter().flatten()) {
+            if result.add(check, run) {
+                break;
+            }
+        }
+        return Ok(result);
+    }
+
+    let parsed = contents.and_then(|contents| {
+        let format = data_format_of(file, options)?;
+        let documents = parse_documents(&contents, format, options.parse)?;
+        Ok(ParsedData {
+            format,
+            raw: contents.into(),
+            documents,
+        })
+    });
+    let data = match parsed {
+        Ok(data) => data,
+        Err(e) if options.continue_on_parse_error => {
+            return Ok(EvalResult {
+                data_file,
+                errors: Vec::new(),
+                parse_error: Some(parse_error_finding(&e)),
+                invocations: 0,
+            });
+        }
+        Err(e) => return Err(e.wrap_err("failed to parse data file")),
+    };
+
+    let mut result = EvalResult {
+        data_file: data_file.clone(),
+        errors: Vec::new(),
+        parse_error: None,
+        invocations: 0,
+    };
     // TODO: Test with parallelism of checks as well?
+    for ((check, key), cached) in checks.iter().zip(&cache_keys).zip(&mut cached) {
+        let run = match cached.take() {
+            Some(run) => run,
+            None => {
+                let started = Instant::now();
+                let run = perform_check(options, file, &data, check)
+                    .wrap_err_with(|| format!("failed to run check: {}", check.path.display()))?;
+                if let Some(timings) = options.timings {
+                    timings.record_check(&check.path, started.elapsed());
+                }
+                if let Some((key, cache)) = key.as_deref().zip(options.cache) {
+                    cache.put(key, &run);
+                }
+                run
+            }
+        };
+        if result.add(check, run) {
+            debug!(
+                path = %file.display(),
+                check = %check.path.display(),
+                "skipping remaining checks after fatal finding",
+            );
+            break;
+        }
+    }
+
+    Ok(result)
+}
+
+/// The finding reported for a data file that fails to parse, with `--continue-on-parse-error`.
+fn parse_error_finding(error: &eyre::Report) -> CheckError {
+    CheckError {
+        severity: CheckSeverity::Error,
+        error: format!("{error:#}"),
+        location: None,
+        id: Some(PARSE_ERROR_ID.to_string()),
+        document_index: None,
+        function: None,
+        fix: None,
+    }
+}
+
+/// The name the findings of `--aggregate` are reported under, as they aren't of any one data file.
+const AGGREGATE_FILE: &str = "<aggregate>";
+
+/// Runs every check once against the documents of all data files together, for `--aggregate`.
+///
+/// Data files that fail to parse are reported on their own with `--continue-on-parse-error`, and
+/// are left out of the documents the checks see.
+fn check_aggregate<'a>(
+    data_files: &[PathBuf],
+    checks: &'a [SourceCode],
+    options: &CheckFileOptions,
+    progress: &ProgressBar,
+) -> Result<Vec<EvalResult<'a>>> {
+    let parsed = data_files
+        .par_iter()
+        .map(|file| {
+            if options.lua.is_cancelled() {
+                return None;
+            }
+            let documents = read_data_file(file, options).and_then(|contents| {
+                let format = data_format_of(file, options)?;
+                parse_documents(&contents, format, options.parse)
+            });
+            progress.inc(1);
+            Some((file, documents))
+        })
+        .collect::<Option<Vec<_>>>();
+    let parsed = parsed.ok_or(CancelledError)?;
+    let mut results = Vec::new();
+    let mut documents = Vec::new();
+    for (file, parsed) in parsed {
+        match parsed {
+            Ok(docs) => documents.extend(docs.into_iter().enumerate().map(|(i, d)| (file, i, d))),
+            Err(e) if options.continue_on_parse_error => results.push(EvalResult {
+                data_file: file.clone(),
+                errors: Vec::new(),
+                parse_error: Some(parse_error_finding(&e)),
+                invocations: 0,
+            }),
+            Err(e) => {
+                return Err(e
+                    .wrap_err("failed to parse data file")
+                    .wrap_err(format!("checking data file: {}", file.display())));
+            }
+        }
+    }
+
+    let mut result = EvalResult {
+        data_file: PathBuf::from(AGGREGATE_FILE),
+        errors: Vec::new(),
+        parse_error: None,
+        invocations: 0,
+    };
     for check in checks {
-        let res = perform_check(lua.clone(), file, &documents, check)
+        ensure!(!options.lua.is_cancelled(), CancelledError);
+        let run = perform_aggregate_check(options, &documents, check)
             .wrap_err_with(|| format!("failed to run check: {}", check.path.display()))?;
-        if !res.is_empty() {
-            results.push((check, res));
+        if result.add(check, run) {
+            debug!(check = %check.path.display(), "skipping remaining checks after fatal finding");
+            break;
         }
     }
-
+    results.push(result);
     Ok(results)
 }
 
-fn parse_data(lua: &Lua, path: &Path) -> Result<Vec<mlua::Value>> {
-    let contents = std::fs::read(path).wrap_err("failed to read data file")?;
-    let ext = path.extension().and_then(|e| e.to_str());
-    if ext.is_some_and(|s| s.eq_ignore_ascii_case("json")) {
-        // We have a simple JSON document: there is only 1 document per file.
-        let value: serde_json::Value =
-            serde_json::from_slice(&contents).wrap_err("failed to parse JSON")?;
-        let value = lua
-            .to_value(&value)
-            .map_err(|e| eyre!("failed to serialize JSON to Lua value: {e}"))
-            .wrap_err("failed to convert JSON to Lua value")?;
-        Ok(vec![value])
-    } else if ext.is_some_and(|s| s.eq_ignore_ascii_case("toml")) {
-        // We have a simple TOML document: there is only 1 document per file.
-        let value: serde_json::Value =
-            toml::from_slice(&contents).wrap_err("failed to parse TOML")?;
-        let value = lua
-            .to_value(&value)
-            .map_err(|e| eyre!("failed to serialize TOML to Lua value: {e}"))
-            .wrap_err("failed to convert TOML to Lua value")?;
-        Ok(vec![value])
-    } else if ext.is_some_and(|s| s.eq_ignore_ascii_case("yml") || s.eq_ignore_ascii_case("yaml")) {
-        // We may have multiple YAML documents in a single file.
-        let deserializer = serde_norway::Deserializer::from_slice(&contents);
-        let mut values = Vec::with_capacity(1);
-        for de in deserializer {
-            let value: serde_json::Value =
-                serde_json::Value::deserialize(de).wrap_err("failed to parse YAML document")?;
-            let value = lua
-                .to_value(&value)
-                .map_err(|e| eyre!("failed to serialize YAML to Lua value: {e}"))
-                .wrap_err("failed to convert YAML to Lua value")?;
-            values.push(value);
-        }
-        Ok(values)
+/// Calls the check functions once with all documents, as a sequence of
+/// `{ path = ..., index = ..., document = ... }` tables in data file order.
+///
+/// The index is that of the document within its data file, starting at 0.
+fn perform_aggregate_check(
+    options: &CheckFileOptions,
+    documents: &[(&PathBuf, usize, serde_json::Value)],
+    check: &SourceCode,
+) -> Result<CheckRun> {
+    let lua = check_lua(options, check)?;
+    let to_lua = |e: mlua::Error| eyre!("failed to convert documents to Lua: {e}");
+    let sequence = lua.create_table().map_err(to_lua)?;
+    for (file, index, document) in documents {
+        let entry = lua
+            .create_table_from([
+                (
+                    "path",
+                    lua.to_value(&file.to_string_lossy()).map_err(to_lua)?,
+                ),
+                ("index", mlua::Value::Integer(*index as i64)),
+                ("document", lua.to_value(document).map_err(to_lua)?),
+            ])
+            .map_err(to_lua)?;
+        sequence.push(entry).map_err(to_lua)?;
+    }
+    let context = create_context(
+        &lua,
+        options.context_input,
+        Path::new(AGGREGATE_FILE),
+        "aggregate",
+        None,
+        check,
+    )?;
+    let findings = check_document(
+        &lua,
+        options,
+        check,
+        &mlua::Value::Table(sequence),
+        None,
+        &context,
+    )?;
+    Ok(CheckRun {
+        invocations: 1,
+        findings,
+    })
+}
+
+/// Reads the contents of the data file, decompressed, from memory or from disk.
+fn read_data_file(file: &Path, options: &CheckFileOptions) -> Result<Vec<u8>> {
+    let contents = match options.in_memory.get(file) {
+        Some(data) => data.contents.clone(),
+        None => retry_io(options.read_retries, || std::fs::read(file))
+            .wrap_err("failed to read data file")?,
+    };
+    decompress(file, contents)
+}
+
+/// The format to parse the data file as.
+fn data_format_of(file: &Path, options: &CheckFileOptions) -> Result<DataFormat> {
+    match options.in_memory.get(file) {
+        Some(data) => data
+            .format
+            .ok_or_else(|| eyre!("unrecognised format of data not read from disk")),
+        None => options
+            .data_extensions
+            .format_of(file)
+            .ok_or_else(|| eyre!("unrecognised file extension")),
+    }
+}
+
+/// Creates a Lua state for running checks, with the memory limit applied.
+fn new_lua(options: &CheckFileOptions) -> Result<Lua> {
+    let lua = options.lua.new_lua()?;
+    if options.memory_limit > 0 {
+        lua.set_memory_limit(options.memory_limit)
+            .map_err(|e| eyre!("failed to set Lua memory limit: {e}"))?;
+    }
+    Ok(lua)
+}
+
+/// A Lua state with the check loaded into it.
+///
+/// The first state is the one the check was discovered in, so that its source isn't run twice.
+fn check_lua(options: &CheckFileOptions, check: &SourceCode) -> Result<Lua> {
+    if let Some(lua) = check.take_loaded() {
+        if options.memory_limit > 0 {
+            lua.set_memory_limit(options.memory_limit)
+                .map_err(|e| eyre!("failed to set Lua memory limit: {e}"))?;
+        }
+        return Ok(lua);
+    }
+    let lua = new_lua(options)?;
+    check.load_into(&lua).wrap_err_with(|| {
+        format!(
+            "failed to load check source code from file: {}",
+            check.path.display()
+        )
+    })?;
+    Ok(lua)
+}
+
+/// Creates the context table passed to the check functions along with each document.
+///
+/// The format is as named in Lua, e.g. `yaml`. Without raw contents, `context.raw` is nil. The
+/// input is that of `--context-json`, if given.
+pub(crate) fn create_context(
+    lua: &Lua,
+    input: Option<&serde_json::Value>,
+    doc_file: &Path,
+    format: &str,
+    raw: Option<&Arc<[u8]>>,
+    check: &SourceCode,
+) -> Result<mlua::Value> {
+    let context = lua
+        .create_table_from([
+            ("check_file", check.path.to_string_lossy()),
+            ("document_file", doc_file.to_string_lossy()),
+            ("format", format.into()),
+        ])
+        .map_err(|e| eyre!("failed to create context table: {e}"))?;
+    if let Some(input) = input {
+        let input = lua
+            .to_value(input)
+            .map_err(|e| eyre!("failed to convert context input to Lua: {e}"))?;
+        context
+            .set("input", input)
+            .map_err(|e| eyre!("failed to set context input: {e}"))?;
+    }
+    let Some(raw) = raw.cloned() else {
+        return Ok(mlua::Value::Table(context));
+    };
+    // The raw contents may be large, so only copy them into Lua once they're used.
+    let index = lua
+        .create_function(move |l, (context, key): (mlua::Table, mlua::String)| {
+            if key.as_bytes() != b"raw".as_slice() {
+                return Ok(mlua::Value::Nil);
+            }
+            let raw = mlua::Value::String(l.create_string(&*raw)?);
+            context.raw_set("raw", &raw)?;
+            Ok(raw)
+        })
+        .map_err(|e| eyre!("failed to create context index function: {e}"))?;
+    let metatable = lua
+        .create_table_from([("__index", index)])
+        .map_err(|e| eyre!("failed to create context metatable: {e}"))?;
+    context
+        .set_metatable(Some(metatable))
+        .map_err(|e| eyre!("failed to set context metatable: {e}"))?;
+    Ok(mlua::Value::Table(context))
+}
+
+/// Calls every check function of the check on the document.
+fn check_document(
+    lua: &Lua,
+    options: &CheckFileOptions,
+    check: &SourceCode,
+    doc: &mlua::Value,
+    document_index: Option<usize>,
+    context: &mlua::Value,
+) -> Result<Vec<CheckError>> {
+    let mut errors = Vec::new();
+    for function in check.check_functions() {
+        let mut res = check.call_check_function(lua, function, doc, document_index, context)?;
+        // Findings of the plain check function are left untagged, as they always were.
+        if function != options.check_fn {
+            for error in &mut res {
+                error.function = Some(function.clone());
+            }
+        }
+        errors.extend(res);
+    }
+    Ok(errors)
+}
+
+/// Runs the check against every document of the data file, in a Lua state of its own.
+///
+/// With `--parallel-documents`, every document is checked in parallel, each in a state of its own.
+fn perform_check(
+    options: &CheckFileOptions,
+    doc_file: &Path,
+    data: &ParsedData,
+    check: &SourceCode,
+) -> Result<CheckRun> {
+    let (format, raw) = (data.format, Some(&data.raw));
+    let format_name = format.name().to_ascii_lowercase();
+    // Single-document files don't need to tell the user which document had the errors.
+    let multi_document = data.documents.len() > 1;
+    let check_documents = |lua: &Lua, documents: &[serde_json::Value], first: usize| {
+        let context = create_context(
+            lua,
+            options.context_input,
+            doc_file,
+            &format_name,
+            raw,
+            check,
+        )?;
+        let mut errors = Vec::new();
+        for (index, doc) in (first..).zip(documents) {
+            let doc = lua
+                .to_value(doc)
+                .map_err(|e| eyre!("failed to convert {format} to Lua value: {e}"))?;
+            errors.extend(check_document(
+                lua,
+                options,
+                check,
+                &doc,
+                multi_document.then_some(index),
+                &context,
+            )?);
+        }
+        Ok::<_, eyre::Report>(errors)
+    };
+    let errors = if options.parallel_documents {
+        data.documents
+            .par_iter()
+            .enumerate()
+            .map(|(index, doc)| {
+                check_documents(
+                    &check_lua(options, check)?,
+                    std::slice::from_ref(doc),
+                    index,
+                )
+            })
+            // Collecting keeps the findings in document order.
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect()
     } else {
-        bail!("unrecognised file extension")
+        check_documents(&check_lua(options, check)?, &data.documents, 0)?
+    };
+
+    Ok(CheckRun {
+        invocations: data.documents.len(),
+        findings: errors,
+    })
+}
+
+/// A data file, parsed into its documents.
+struct ParsedData {
+    format: DataFormat,
+    /// The contents of the file before parsing: decompressed if it's compressed, as fetched if it's
+    /// a URL, or as given with `--data`.
+    raw: Arc<[u8]>,
+    /// The documents, yet to be converted into the Lua state of each check that checks them.
+    documents: Vec<serde_json::Value>,
+}
+
+/// Decompresses the contents of the data file if it's compressed with gzip, or else returns them
+/// as they are.
+fn decompress(file: &Path, contents: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_gzip(file) {
+        return Ok(contents);
+    }
+    let mut decompressed = Vec::new();
+    GzDecoder::new(contents.as_slice())
+        .read_to_end(&mut decompressed)
+        .wrap_err("failed to decompress gzip data file")?;
+    Ok(decompressed)
+}
+
+/// The options that may change the findings of a check, which are part of every cache key.
+#[derive(Debug, Serialize)]
+struct CacheOptions<'a> {
+    check_fn: &'a str,
+    now: Option<i64>,
+    /// The current UTC date, unless `now` is pinned, so that the findings of checks using `Now()`
+    /// are only reused on the same day.
+    today: Option<String>,
+    lua_path: &'a [PathBuf],
+    allow_unsafe: bool,
+    seed: Option<u64>,
+    /// The values of the exposed environment variables, by name.
+    env: Vec<(&'a str, Option<String>)>,
+    prelude: Option<(&'a Path, &'a str)>,
+    data_extensions: &'a DataExtensions,
+    parse: ParseOptions<'a>,
+    context_json: Option<&'a serde_json::Value>,
+}
+
+/// Options for how to split data files into documents.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+struct ParseOptions<'a> {
+    /// Whether the elements of a top-level JSON array are separate documents.
+    json_array_as_documents: bool,
+    /// The line that separates documents in TOML files, if they may hold more than one.
+    toml_delimiter: Option<&'a str>,
+    /// Whether whole decimal numbers are converted into integers.
+    normalize_numbers: bool,
+    /// Whether the body of Markdown files is added to their document as `__body`.
+    markdown_body: bool,
+    /// Whether Markdown files without front matter are an empty document, rather than none.
+    markdown_without_front_matter: bool,
+}
+
+/// Parses the data file into its documents.
+///
+/// Every format is parsed into JSON values first, so numbers are converted the same way for all of
+/// them: integers that fit in an `i64` stay integers, and every other number (including integers
+/// beyond `u64`, and all decimals) is a float. See [`normalize_numbers`] for the exception.
+fn parse_documents(
+    contents: &[u8],
+    format: DataFormat,
+    options: ParseOptions,
+) -> Result<Vec<serde_json::Value>> {
+    let mut values = match format {
+        DataFormat::Json => {
+            // We have a simple JSON document: there is only 1 document per file, unless we're asked
+            // to split up a top-level array.
+            let value: serde_json::Value =
+                serde_json::from_slice(contents).wrap_err("failed to parse JSON")?;
+            match value {
+                serde_json::Value::Array(values) if options.json_array_as_documents => values,
+                value => vec![value],
+            }
+        }
+        DataFormat::JsonStream => {
+            // Every JSON value in the stream is its own document.
+            serde_json::Deserializer::from_slice(contents)
+                .into_iter::<serde_json::Value>()
+                .enumerate()
+                .map(|(index, value)| {
+                    value.wrap_err_with(|| format!("failed to parse JSON value {}", index + 1))
+                })
+                .collect::<Result<Vec<_>>>()?
+        }
+        DataFormat::Toml => match options.toml_delimiter {
+            // We have a simple TOML document: there is only 1 document per file.
+            None => {
+                let value: serde_json::Value =
+                    toml::from_slice(contents).wrap_err("failed to parse TOML")?;
+                vec![value]
+            }
+            // Otherwise, every chunk between delimiter lines is its own document.
+            Some(delimiter) => {
+                let contents =
+                    std::str::from_utf8(contents).wrap_err("TOML file is not valid UTF-8")?;
+                let mut chunks = vec![String::new()];
+                for line in contents.split_inclusive('\n') {
+                    if line.trim_end() == delimiter {
+                        chunks.push(String::new());
+                    } else if let Some(chunk) = chunks.last_mut() {
+                        chunk.push_str(line);
+                    }
+                }
+                chunks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, chunk)| !chunk.trim().is_empty())
+                    .map(|(index, chunk)| {
+                        toml::from_str(chunk).wrap_err_with(|| {
+                            format!("failed to parse TOML document {}", index + 1)
+                        })
+                    })
+                    .collect::<Result<Vec<serde_json::Value>>>()?
+            }
+        },
+        DataFormat::Yaml => {
+            // We may have multiple YAML documents in a single file.
+            let deserializer = serde_norway::Deserializer::from_slice(contents);
+            let mut values = Vec::with_capacity(1);
+            for de in deserializer {
+                let value: serde_json::Value =
+                    serde_json::Value::deserialize(de).wrap_err("failed to parse YAML document")?;
+                values.push(value);
+            }
+            values
+        }
+        DataFormat::Xml => {
+            // XML has a single root element, so there is only 1 document per file.
+            let contents = std::str::from_utf8(contents).wrap_err("XML file is not valid UTF-8")?;
+            let document = roxmltree::Document::parse(contents).wrap_err("failed to parse XML")?;
+            let root = document.root_element();
+            let mut value = serde_json::Map::with_capacity(1);
+            value.insert(root.tag_name().name().to_string(), xml_to_json(root));
+            vec![serde_json::Value::Object(value)]
+        }
+        DataFormat::Csv | DataFormat::Tsv => {
+            // Every row is its own document, as an object keyed by the header columns.
+            let delimiter = if format == DataFormat::Tsv {
+                b'\t'
+            } else {
+                b','
+            };
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .from_reader(contents);
+            let headers = reader
+                .headers()
+                .wrap_err_with(|| format!("failed to parse {format} header"))?
+                .clone();
+            let mut values = Vec::new();
+            for (index, record) in reader.records().enumerate() {
+                let record = record
+                    .wrap_err_with(|| format!("failed to parse {format} record {}", index + 1))?;
+                let row = headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(k, v)| (k.to_string(), serde_json::Value::String(v.to_string())))
+                    .collect();
+                values.push(serde_json::Value::Object(row));
+            }
+            values
+        }
+        DataFormat::Hcl => {
+            // HCL bodies are a single document. Blocks become objects nested by their type and then
+            // each of their labels, e.g. `resource "a" "b" {}` is `{"resource": {"a": {"b": {}}}}`.
+            // Repeated blocks with the same type and labels become an array of their bodies.
+            let value: serde_json::Value =
+                hcl::from_slice(contents).wrap_err("failed to parse HCL")?;
+            vec![value]
+        }
+        DataFormat::Env => {
+            // A `.env` file is a single document: an object of its keys and string values.
+            let contents = std::str::from_utf8(contents).wrap_err("failed to parse env file")?;
+            vec![parse_env(contents)?]
+        }
+        DataFormat::Markdown => {
+            // Only the YAML front matter is structured, so it's the single document, if any.
+            let contents =
+                std::str::from_utf8(contents).wrap_err("Markdown file is not valid UTF-8")?;
+            let (front_matter, body) = split_front_matter(contents);
+            let mut document = match front_matter {
+                Some(front_matter) => {
+                    match serde_norway::from_str(front_matter)
+                        .wrap_err("failed to parse YAML front matter")?
+                    {
+                        serde_json::Value::Null => serde_json::Map::new(),
+                        serde_json::Value::Object(document) => document,
+                        _ => bail!("YAML front matter is not a mapping"),
+                    }
+                }
+                None if options.markdown_without_front_matter => serde_json::Map::new(),
+                None => return Ok(Vec::new()),
+            };
+            if options.markdown_body {
+                document.insert(
+                    "__body".to_string(),
+                    serde_json::Value::String(body.to_string()),
+                );
+            }
+            vec![serde_json::Value::Object(document)]
+        }
+    };
+    if options.normalize_numbers {
+        values.iter_mut().for_each(normalize_numbers);
+    }
+    Ok(values)
+}
+
+/// Converts every float without a fractional part in the value into an integer, e.g. `2.0` into
+/// `2`, for `--normalize-numbers`.
+///
+/// Only floats within ±2^53 are converted, as larger ones may not be the integer they were
+/// written as anymore.
+fn normalize_numbers(value: &mut serde_json::Value) {
+    const MAX_EXACT: f64 = (1u64 << 53) as f64;
+    match value {
+        serde_json::Value::Number(number) => {
+            if let Some(float) = number.as_f64().filter(|_| number.is_f64())
+                && float.fract() == 0.0
+                && float.abs() <= MAX_EXACT
+            {
+                *number = (float as i64).into();
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(normalize_numbers),
+        serde_json::Value::Object(values) => values.values_mut().for_each(normalize_numbers),
+        _ => {}
+    }
+}
+
+/// Splits a Markdown file into its YAML front matter, if any, and its body.
+///
+/// The front matter must start on the first line with `---`, and ends at the next line of just
+/// `---` or `...`. Without such a line, the first line is a thematic break rather than the start
+/// of front matter.
+fn split_front_matter(contents: &str) -> (Option<&str>, &str) {
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    let mut lines = contents.split_inclusive('\n');
+    let Some(first) = lines.next() else {
+        return (None, contents);
+    };
+    if first.trim_end() != "---" {
+        return (None, contents);
+    }
+    let start = first.len();
+    let mut end = start;
+    for line in lines {
+        if matches!(line.trim_end(), "---" | "...") {
+            return (Some(&contents[start..end]), &contents[end + line.len()..]);
+        }
+        end += line.len();
+    }
+    (None, contents)
+}
+
+/// Converts an XML element into a JSON value.
+///
+/// The document's root element is placed under a key of its own name by the caller. Every
+/// element is then mapped as follows:
+///
+///   * Attributes become keys prefixed with `@`, e.g. `<a href="x"/>` is `{"@href": "x"}`.
+///   * Child elements become keys by their local name (i.e. without namespace prefix). Repeated
+///     child elements of the same name become an array, in document order.
+///   * Text content, with surrounding whitespace trimmed, goes under the `#text` key.
+///   * An element with only text becomes that text as a plain string, and an element with no
+///     attributes, children, or text becomes `null`.
+///
+/// All values are strings: no attempt is made at inferring numbers or booleans.
+fn xml_to_json(element: roxmltree::Node) -> serde_json::Value {
+    use serde_json::{Map, Value};
+
+    let mut map = Map::new();
+    for attr in element.attributes() {
+        map.insert(
+            format!("@{}", attr.name()),
+            Value::String(attr.value().into()),
+        );
+    }
+
+    let mut text = String::new();
+    for child in element.children() {
+        if child.is_element() {
+            let name = child.tag_name().name().to_string();
+            let value = xml_to_json(child);
+            match map.get_mut(&name) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    map.insert(name, value);
+                }
+            }
+        } else if let Some(t) = child.text().filter(|_| child.is_text()) {
+            text.push_str(t);
+        }
+    }
+
+    let text = text.trim();
+    match (map.is_empty(), text.is_empty()) {
+        (true, true) => Value::Null,
+        (true, false) => Value::String(text.to_string()),
+        (false, true) => Value::Object(map),
+        (false, false) => {
+            map.insert("#text".to_string(), Value::String(text.to_string()));
+            Value::Object(map)
+        }
     }
 }
@@ -0,0 +1,88 @@
+use crate::check::FailOn;
+use eyre::{Context, Result, ensure};
+use globset::Glob;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// The name of the configuration file we look for.
+pub const CONFIG_FILE_NAME: &str = "checkonaut.toml";
+
+/// Defaults for command-line flags, as read from a `checkonaut.toml` file.
+///
+/// Flags given on the command line always take precedence over the configuration. Relative paths
+/// are resolved against the directory of the configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The default for `--dotfiles`.
+    pub dotfiles: Option<bool>,
+    /// The default for `--dotdirs`.
+    pub dotdirs: Option<bool>,
+    /// The default for `--exclude`, used if none are given.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// The default for `check --timeout`, in seconds.
+    pub timeout: Option<u64>,
+    /// The default for `check --fail-on`.
+    pub fail_on: Option<FailOn>,
+    /// The default for `--lua-path`, used if none are given.
+    #[serde(default)]
+    pub lua_path: Vec<PathBuf>,
+    /// The default for `check --checks`, used if none are given.
+    #[serde(default)]
+    pub checks: Vec<PathBuf>,
+    /// The default for `check --data-ext`, used if none are given.
+    #[serde(default)]
+    pub data_ext: Vec<String>,
+}
+
+impl Config {
+    /// Loads the configuration from the given file, or from the nearest `checkonaut.toml` in the
+    /// current directory or any of its parents.
+    ///
+    /// Without an explicit file, a missing configuration is the same as an empty one.
+    pub fn load(explicit: Option<&Path>) -> Result<Self> {
+        let path = match explicit {
+            Some(path) => {
+                ensure!(
+                    path.is_file(),
+                    "configuration file does not exist: {}",
+                    path.display()
+                );
+                path.to_path_buf()
+            }
+            None => {
+                let cwd = std::env::current_dir().wrap_err("failed to get current directory")?;
+                match cwd
+                    .ancestors()
+                    .map(|dir| dir.join(CONFIG_FILE_NAME))
+                    .find(|path| path.is_file())
+                {
+                    Some(path) => path,
+                    None => return Ok(Self::default()),
+                }
+            }
+        };
+        let contents = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read configuration file: {}", path.display()))?;
+        let mut config: Self = toml::from_str(&contents)
+            .wrap_err_with(|| format!("failed to parse configuration file: {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for path in config.lua_path.iter_mut().chain(&mut config.checks) {
+            *path = dir.join(&*path);
+        }
+        debug!(path = %path.display(), "loaded configuration file");
+        Ok(config)
+    }
+
+    /// The default for `--exclude`, as glob patterns.
+    pub fn exclude_globs(&self) -> Result<Vec<Glob>> {
+        (self.exclude.iter())
+            .map(|pattern| {
+                Glob::new(pattern)
+                    .wrap_err_with(|| format!("invalid exclude in configuration: {pattern}"))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,33 @@
+use crate::lua::{Builtin, SourceCode};
+use clap::Args;
+use eyre::Result;
+
+#[derive(Debug, Args)]
+pub struct Explain {}
+
+impl Explain {
+    pub fn run(self) -> Result<()> {
+        print_module(
+            "checkonaut",
+            "available as `checkonaut` in checks",
+            SourceCode::BUILTINS,
+        );
+        println!();
+        print_module(
+            "checkonaut.test",
+            "available as `checkonaut.test` in tests",
+            SourceCode::TEST_BUILTINS,
+        );
+        Ok(())
+    }
+}
+
+fn print_module(name: &str, note: &str, builtins: &[Builtin]) {
+    println!("{name} ({note}):");
+    let mut builtins = builtins.to_vec();
+    builtins.sort_unstable_by_key(|b| b.name);
+    for builtin in builtins {
+        println!("  {}", builtin.signature);
+        println!("      {}", builtin.description);
+    }
+}
@@ -1,12 +1,29 @@
+use crate::config::CONFIG_FILE_NAME;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
 use rayon::prelude::*;
-use snafu::{ResultExt, Snafu};
-use std::path::{Path, PathBuf};
+use serde::{Serialize, Serializer};
+use snafu::{ResultExt, Snafu, ensure};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+use tracing::debug;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// The name of the file listing glob patterns of paths to ignore.
+pub const IGNORE_FILE_NAME: &str = ".checkonautignore";
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct FileSearcher {
+    data_extensions: DataExtensions,
     include_dotfiles: bool,
     include_dotdirs: bool,
     follow_links: bool,
+    /// Patterns of paths to skip, as if they were in an ignore file in every searched path.
+    exclude: Vec<Glob>,
 
     include_check_files: bool,
     include_test_files: bool,
@@ -14,6 +31,11 @@ pub struct FileSearcher {
 }
 
 impl FileSearcher {
+    pub fn data_extensions(mut self, extensions: DataExtensions) -> Self {
+        self.data_extensions = extensions;
+        self
+    }
+
     pub fn include_dotfiles(mut self, include: bool) -> Self {
         self.include_dotfiles = include;
         self
@@ -24,6 +46,13 @@ impl FileSearcher {
         self
     }
 
+    /// Skips the files and directories matching any of the patterns, as if they were listed in an
+    /// ignore file in each of the searched paths.
+    pub fn exclude(mut self, patterns: Vec<Glob>) -> Self {
+        self.exclude = patterns;
+        self
+    }
+
     pub fn include_check_files(mut self, include: bool) -> Self {
         self.include_check_files = include;
         self
@@ -39,17 +68,26 @@ impl FileSearcher {
         self
     }
 
+    /// Searches the given paths for relevant files.
+    ///
+    /// The files of each type are sorted by path, so the result doesn't depend on how the search
+    /// was parallelised.
     pub fn search<I, J>(self, from_paths: I) -> Result<FileSearchResult, FileSearchError>
     where
         I: IntoParallelIterator<Iter = J, Item = PathBuf>,
         J: ParallelIterator<Item = PathBuf>,
     {
-        from_paths
+        let mut exclude = GlobSetBuilder::new();
+        for glob in &self.exclude {
+            exclude.add(glob.clone());
+        }
+        let exclude = exclude.build().context(InvalidExcludePatternsSnafu)?;
+        let mut result = from_paths
             .into_par_iter()
-            .flat_map(|p| self.find_files(p))
+            .flat_map(|p| self.find_files(p, &exclude))
             .try_fold(FileSearchResult::default, |mut acc, result| match result {
                 Ok(path) => {
-                    let ty = FileTy::derive_from_path(&path);
+                    let ty = FileTy::derive_from_path(&path, &self.data_extensions);
                     match ty {
                         Some(FileTy::Test) => acc.test_files.push(path),
                         Some(FileTy::Check) => acc.check_files.push(path),
@@ -66,58 +104,205 @@ impl FileSearcher {
                 a.data_files.append(&mut b.data_files);
                 Ok(a)
             })
-            .unwrap_or_else(|| Ok(FileSearchResult::default()))
+            .unwrap_or_else(|| Ok(FileSearchResult::default()))?;
+        result.check_files.sort_unstable();
+        result.test_files.sort_unstable();
+        result.data_files.sort_unstable();
+        Ok(result)
     }
 
     fn find_files(
         &self,
         path: PathBuf,
+        exclude: &GlobSet,
     ) -> impl ParallelIterator<Item = Result<PathBuf, FileSearchError>> {
-        walkdir::WalkDir::new(&path)
+        let mut walker = walkdir::WalkDir::new(&path)
             .follow_links(self.follow_links)
-            .into_iter()
-            .par_bridge()
-            .filter_map(move |entry| match entry {
-                // We don't care about the directories themselves; walkdir will enter them for us.
-                Ok(entry) if entry.file_type().is_file() => {
-                    // Period is an ASCII character, so we don't need to care about whether we follow
-                    // UTF-8 in the path :)
-                    let name_bytes = entry.file_name().as_encoded_bytes();
-                    let ty = FileTy::derive_from_byte_name(name_bytes);
-                    let included = match ty {
-                        Some(FileTy::Test) => self.include_test_files,
-                        Some(FileTy::Check) => self.include_check_files,
-                        Some(FileTy::Data) => self.include_data_files,
-                        None => false,
-                    };
-                    let include_dot = if entry.file_type().is_dir() {
-                        self.include_dotdirs
-                    } else {
-                        self.include_dotfiles
-                    };
-                    let included = included && (include_dot || !name_bytes.starts_with(b"."));
-
-                    if included {
-                        Some(Ok(entry.into_path()))
-                    } else {
-                        None
+            .into_iter();
+        let mut ignores = IgnoreFiles::default();
+        let include_dotdirs = self.include_dotdirs;
+        let exclude = exclude.clone();
+        let walk_path = path.clone();
+        // We walk manually rather than with `filter_entry` so that we can report errors from
+        // reading the ignore files.
+        let entries = std::iter::from_fn(move || {
+            loop {
+                let entry = match walker.next()? {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        return Some(Err(err).context(FailedDirectoryWalkSnafu {
+                            path: walk_path.clone(),
+                        }));
+                    }
+                };
+                // The paths we're given are walked even if they're dot directories themselves.
+                if !include_dotdirs
+                    && entry.depth() > 0
+                    && entry.file_type().is_dir()
+                    && entry.file_name().as_encoded_bytes().starts_with(b".")
+                {
+                    walker.skip_current_dir();
+                    continue;
+                }
+                // As with ignore files, the patterns match the path within the searched path, or
+                // the plain file name.
+                let relative = entry
+                    .path()
+                    .strip_prefix(&walk_path)
+                    .unwrap_or(entry.path());
+                if entry.depth() > 0
+                    && (exclude.is_match(relative) || exclude.is_match(entry.file_name()))
+                {
+                    if entry.file_type().is_dir() {
+                        walker.skip_current_dir();
+                    }
+                    continue;
+                }
+                match ignores.is_ignored(entry.path(), entry.depth()) {
+                    Ok(false) => return Some(Ok(entry)),
+                    Ok(true) => {
+                        if entry.file_type().is_dir() {
+                            walker.skip_current_dir();
+                        }
                     }
+                    Err(err) => return Some(Err(err)),
                 }
+            }
+        });
 
-                Ok(_) => {
-                    // Ignore this item; we'll either visit the values inside that we care about,
-                    // or it isn't something that we've configured ourselves to care about.
+        entries.par_bridge().filter_map(move |entry| match entry {
+            // We don't care about the directories themselves; walkdir will enter them for us.
+            Ok(entry) if entry.file_type().is_file() => {
+                // Period is an ASCII character, so we don't need to care about whether we follow
+                // UTF-8 in the path :)
+                let name_bytes = entry.file_name().as_encoded_bytes();
+                let ty = FileTy::derive_from_path(entry.path(), &self.data_extensions);
+                let included = match ty {
+                    Some(FileTy::Test) => self.include_test_files,
+                    Some(FileTy::Check) => self.include_check_files,
+                    Some(FileTy::Data) => self.include_data_files,
+                    None => false,
+                };
+                let included = included && (self.include_dotfiles || !name_bytes.starts_with(b"."));
+
+                if included {
+                    Some(Ok(entry.into_path()))
+                } else {
                     None
                 }
+            }
 
-                Err(err) => Some(Err(err).context(FailedDirectoryWalkSnafu {
-                    path: path.to_path_buf(),
-                })),
-            })
+            Ok(_) => {
+                // Ignore this item; we'll either visit the values inside that we care about,
+                // or it isn't something that we've configured ourselves to care about.
+                None
+            }
+
+            Err(err) => Some(Err(err)),
+        })
+    }
+}
+
+/// The parsed `.checkonautignore` files of the directories visited during a walk.
+///
+/// Each ignore file applies to everything below the directory it is in. Its patterns are matched
+/// both against the path relative to that directory and against the plain file name. Empty lines
+/// and lines starting with `#` are skipped.
+#[derive(Debug, Default)]
+struct IgnoreFiles {
+    /// The ignore patterns by directory. Directories without an ignore file map to `None`.
+    by_dir: HashMap<PathBuf, Option<GlobSet>>,
+}
+
+impl IgnoreFiles {
+    /// Whether the given path, found at the given depth of the walk, should be ignored.
+    ///
+    /// Only ignore files in directories within the walk are considered; the walk root itself is
+    /// never ignored.
+    fn is_ignored(&mut self, path: &Path, depth: usize) -> Result<bool, FileSearchError> {
+        let Some(file_name) = path.file_name() else {
+            return Ok(false);
+        };
+        for dir in path.ancestors().skip(1).take(depth) {
+            let Some(globs) = self.globs_for(dir)? else {
+                continue;
+            };
+            let relative = path.strip_prefix(dir).unwrap_or(path);
+            if globs.is_match(relative) || globs.is_match(file_name) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn globs_for(&mut self, dir: &Path) -> Result<Option<&GlobSet>, FileSearchError> {
+        if !self.by_dir.contains_key(dir) {
+            let globs = Self::read(dir)?;
+            self.by_dir.insert(dir.to_path_buf(), globs);
+        }
+        Ok(self.by_dir.get(dir).and_then(Option::as_ref))
+    }
+
+    fn read(dir: &Path) -> Result<Option<GlobSet>, FileSearchError> {
+        let path = dir.join(IGNORE_FILE_NAME);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context(FailedReadIgnoreFileSnafu { path }),
+        };
+
+        let mut builder = GlobSetBuilder::new();
+        for pattern in contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let glob = Glob::new(pattern).context(InvalidIgnorePatternSnafu {
+                path: path.clone(),
+                pattern,
+            })?;
+            builder.add(glob);
+        }
+        let globs = builder
+            .build()
+            .context(InvalidIgnorePatternSnafu { path, pattern: "" })?;
+        Ok(Some(globs))
+    }
+}
+
+/// Expands any glob patterns among the given paths into the paths they match.
+///
+/// Paths without glob metacharacters (`*`, `?`, `[`) are passed through unchanged, even if they
+/// don't exist. A glob that matches nothing is an error, as it is most likely a typo.
+pub fn expand_globs(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, FileSearchError> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if !pattern.contains(['*', '?', '[']) {
+            expanded.push(path);
+            continue;
+        }
+
+        let before = expanded.len();
+        let matches = glob::glob(&pattern).context(InvalidGlobSnafu {
+            pattern: pattern.as_ref(),
+        })?;
+        for entry in matches {
+            expanded.push(entry.context(FailedGlobSnafu {
+                pattern: pattern.as_ref(),
+            })?);
+        }
+        ensure!(
+            expanded.len() > before,
+            NoGlobMatchesSnafu {
+                pattern: pattern.as_ref()
+            }
+        );
     }
+    Ok(expanded)
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct FileSearchResult {
     pub check_files: Vec<PathBuf>,
     pub test_files: Vec<PathBuf>,
@@ -131,6 +316,34 @@ pub enum FileSearchError {
         path: PathBuf,
         source: walkdir::Error,
     },
+
+    #[snafu(display("Failed to read ignore file '{}'", path.display()))]
+    FailedReadIgnoreFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Invalid glob pattern '{pattern}'"))]
+    InvalidGlob {
+        pattern: String,
+        source: glob::PatternError,
+    },
+
+    #[snafu(display("Failed to expand glob pattern '{pattern}'"))]
+    FailedGlob {
+        pattern: String,
+        source: glob::GlobError,
+    },
+
+    #[snafu(display("Glob pattern '{pattern}' did not match any files"))]
+    NoGlobMatches { pattern: String },
+
+    #[snafu(display("Invalid exclude patterns"))]
+    InvalidExcludePatterns { source: globset::Error },
+
+    #[snafu(display("Invalid pattern '{pattern}' in ignore file '{}'", path.display()))]
+    InvalidIgnorePattern {
+        path: PathBuf,
+        pattern: String,
+        source: globset::Error,
+    },
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -141,36 +354,397 @@ enum FileTy {
 }
 
 impl FileTy {
-    fn derive_from_path(path: &Path) -> Option<Self> {
+    fn derive_from_path(path: &Path, data_extensions: &DataExtensions) -> Option<Self> {
         let name_bytes = path.file_name()?.as_encoded_bytes();
         if name_bytes.ends_with(b"_test.lua") {
             Some(FileTy::Test)
         } else if name_bytes.ends_with(b".lua") {
             Some(FileTy::Check)
-        } else if name_bytes.ends_with(b".json")
-            || name_bytes.ends_with(b".yaml")
-            || name_bytes.ends_with(b".yml")
-            || name_bytes.ends_with(b".toml")
-        {
+        } else if data_extensions.format_of(path).is_some() {
             Some(FileTy::Data)
         } else {
             None
         }
     }
+}
 
-    fn derive_from_byte_name(name_bytes: &[u8]) -> Option<Self> {
-        if name_bytes.ends_with(b"_test.lua") {
-            Some(FileTy::Test)
-        } else if name_bytes.ends_with(b".lua") {
-            Some(FileTy::Check)
-        } else if name_bytes.ends_with(b".json")
-            || name_bytes.ends_with(b".yaml")
-            || name_bytes.ends_with(b".yml")
-            || name_bytes.ends_with(b".toml")
-        {
-            Some(FileTy::Data)
-        } else {
-            None
+/// A data file that isn't read from disk, e.g. one fetched from a URL or given inline.
+#[derive(Debug, Clone)]
+pub struct InMemoryData {
+    pub contents: Vec<u8>,
+    /// The format, if it could be detected.
+    pub format: Option<DataFormat>,
+}
+
+/// The format of a data file, as detected from its file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum DataFormat {
+    Json,
+    /// Any number of JSON values, separated only by whitespace (if anything).
+    JsonStream,
+    Yaml,
+    Toml,
+    Xml,
+    Csv,
+    Tsv,
+    Hcl,
+    /// `KEY=VALUE` lines, as in `.env` files.
+    Env,
+    /// Markdown, of which only the YAML front matter (and optionally the body) is checked.
+    Markdown,
+}
+
+impl DataFormat {
+    /// All known data formats.
+    pub const ALL: &[DataFormat] = &[
+        Self::Json,
+        Self::JsonStream,
+        Self::Yaml,
+        Self::Toml,
+        Self::Xml,
+        Self::Csv,
+        Self::Tsv,
+        Self::Hcl,
+        Self::Env,
+        Self::Markdown,
+    ];
+
+    fn from_extension(ext: &[u8]) -> Option<Self> {
+        Self::ALL.iter().copied().find(|f| {
+            f.extensions()
+                .iter()
+                .any(|e| ext.eq_ignore_ascii_case(e.as_bytes()))
+        })
+    }
+
+    /// Finds the format by a media type, e.g. from a `Content-Type` header, ignoring parameters
+    /// such as the charset.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        let media_type = media_type.split(';').next()?.trim().to_ascii_lowercase();
+        match media_type.as_str() {
+            "application/json" | "text/json" => Some(Self::Json),
+            "application/x-ndjson" | "application/jsonl" | "application/json-seq" => {
+                Some(Self::JsonStream)
+            }
+            "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => {
+                Some(Self::Yaml)
+            }
+            "application/toml" => Some(Self::Toml),
+            "application/xml" | "text/xml" => Some(Self::Xml),
+            "text/csv" => Some(Self::Csv),
+            "text/tab-separated-values" => Some(Self::Tsv),
+            "text/markdown" => Some(Self::Markdown),
+            // Structured syntax suffixes, e.g. `application/problem+json`.
+            other if other.ends_with("+json") => Some(Self::Json),
+            other if other.ends_with("+xml") => Some(Self::Xml),
+            other if other.ends_with("+yaml") => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Finds the format by its name, as given by [`DataFormat::name`], ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|f| f.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The file extensions (without leading period) recognised for this format.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Json => &["json"],
+            Self::JsonStream => &["jsons", "jsonl", "ndjson"],
+            Self::Yaml => &["yaml", "yml"],
+            Self::Toml => &["toml"],
+            Self::Xml => &["xml"],
+            Self::Csv => &["csv"],
+            Self::Tsv => &["tsv"],
+            Self::Hcl => &["hcl", "tf"],
+            // This also matches files named just `.env`, though those need `--dotfiles`.
+            Self::Env => &["env"],
+            Self::Markdown => &["md", "markdown"],
+        }
+    }
+
+    /// A short, human-readable name of the format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Json => "JSON",
+            Self::JsonStream => "JSONS",
+            Self::Yaml => "YAML",
+            Self::Toml => "TOML",
+            Self::Xml => "XML",
+            Self::Csv => "CSV",
+            Self::Tsv => "TSV",
+            Self::Hcl => "HCL",
+            Self::Env => "ENV",
+            Self::Markdown => "Markdown",
         }
     }
 }
+
+impl fmt::Display for DataFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Runs the IO operation, retrying it up to `retries` more times if it fails with an error that
+/// may be transient, e.g. on a network filesystem.
+///
+/// Errors that won't go away by trying again, like a missing file, are returned immediately. The
+/// wait between attempts starts at 50 ms, and doubles after every attempt.
+pub(crate) fn retry_io<T>(retries: u32, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut backoff = Duration::from_millis(50);
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                debug!(error = %e, attempt, "retrying IO operation after error");
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    !matches!(
+        error.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::IsADirectory
+            | io::ErrorKind::Unsupported
+    )
+}
+
+/// Whether the file is a test file, i.e. its name ends in `_test.lua`.
+pub fn is_test_file(path: &Path) -> bool {
+    FileTy::derive_from_path(path, &DataExtensions::default()) == Some(FileTy::Test)
+}
+
+/// Whether the file is compressed with gzip, as detected from a trailing `.gz` extension.
+///
+/// The format of a compressed data file is detected from the extension before the `.gz`.
+pub fn is_gzip(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| strip_gzip_extension(name.as_encoded_bytes()).is_some())
+}
+
+fn strip_gzip_extension(name_bytes: &[u8]) -> Option<&[u8]> {
+    let stem = name_bytes.len().checked_sub(3)?;
+    name_bytes[stem..]
+        .eq_ignore_ascii_case(b".gz")
+        .then(|| &name_bytes[..stem])
+}
+
+/// Whether the file name is that of an ignore file, a configuration file, or a Lua check or test
+/// file, none of which are data files by a format override.
+fn is_own_file(name_bytes: &[u8]) -> bool {
+    name_bytes == IGNORE_FILE_NAME.as_bytes()
+        || name_bytes == CONFIG_FILE_NAME.as_bytes()
+        || name_bytes.ends_with(b".lua")
+}
+
+/// The bytes after the last period in a file name, if any.
+fn extension_of(name_bytes: &[u8]) -> Option<&[u8]> {
+    let dot = name_bytes.iter().rposition(|&b| b == b'.')?;
+    Some(&name_bytes[dot + 1..])
+}
+
+/// A custom file extension to parse as a known data format, as given by `ext=format`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataExtension {
+    /// The extension, without leading period, in lowercase.
+    extension: String,
+    format: DataFormat,
+}
+
+impl FromStr for DataExtension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (extension, format) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected 'ext=format', got '{s}'"))?;
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        if extension.is_empty() {
+            return Err(format!("missing extension in '{s}'"));
+        }
+        let format = format.parse()?;
+        // Remapping a built-in extension is caught here, so it's an error when parsing the flag.
+        // Two conflicting custom mappings are only caught together, by `DataExtensions::new`.
+        match DataFormat::from_extension(extension.as_bytes()) {
+            Some(existing) if existing != format => Err(format!(
+                "extension '{extension}' can't be parsed as {format}, as it is already parsed as \
+                 {existing}"
+            )),
+            _ => Ok(Self { extension, format }),
+        }
+    }
+}
+
+impl FromStr for DataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_name(s).ok_or_else(|| {
+            let known = Self::ALL
+                .iter()
+                .map(|f| f.name().to_ascii_lowercase())
+                .collect::<Vec<_>>();
+            format!(
+                "unknown format '{s}', expected one of: {}",
+                known.join(", ")
+            )
+        })
+    }
+}
+
+/// A glob of paths to parse as a known data format, regardless of their extension, as given by
+/// `glob=format`.
+#[derive(Debug, Clone)]
+pub struct FormatOverride {
+    glob: Glob,
+    matcher: GlobMatcher,
+    format: DataFormat,
+}
+
+impl Serialize for FormatOverride {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.glob.glob(), self.format).serialize(serializer)
+    }
+}
+
+impl FormatOverride {
+    /// Whether the path, or any trailing part of it, matches the glob.
+    ///
+    /// This way, `manifests/*.cfg` matches `deploy/manifests/a.cfg` wherever the search started,
+    /// and `*.cfg` matches by file name alone.
+    fn matches(&self, path: &Path) -> bool {
+        let components = path
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect::<Vec<_>>();
+        (0..components.len()).any(|i| {
+            self.matcher
+                .is_match(components[i..].iter().collect::<PathBuf>())
+        })
+    }
+}
+
+impl PartialEq for FormatOverride {
+    fn eq(&self, other: &Self) -> bool {
+        self.glob == other.glob && self.format == other.format
+    }
+}
+
+impl Eq for FormatOverride {}
+
+impl FromStr for FormatOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, format) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected 'glob=format', got '{s}'"))?;
+        if pattern.is_empty() {
+            return Err(format!("missing glob in '{s}'"));
+        }
+        // Like in shells, `*` doesn't cross directories; `**` does.
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format!("invalid glob '{pattern}': {e}"))?;
+        let format = format.parse()?;
+        Ok(Self {
+            matcher: glob.compile_matcher(),
+            glob,
+            format,
+        })
+    }
+}
+
+/// The built-in data file extensions, along with any custom extensions mapped to a format.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct DataExtensions {
+    custom: BTreeMap<String, DataFormat>,
+    /// Globs whose paths are a format regardless of extension, the first match taking precedence.
+    overrides: Vec<FormatOverride>,
+}
+
+impl DataExtensions {
+    /// Builds the extensions from the given custom mappings.
+    ///
+    /// Mapping an extension to two different formats is an error, including when the extension is
+    /// already known to be another format.
+    pub fn new(mappings: &[DataExtension]) -> Result<Self, DataExtensionError> {
+        let mut custom = BTreeMap::new();
+        for DataExtension { extension, format } in mappings {
+            let existing = DataFormat::from_extension(extension.as_bytes())
+                .or_else(|| custom.get(extension).copied());
+            if let Some(existing) = existing {
+                ensure!(
+                    existing == *format,
+                    ConflictingDataExtensionSnafu {
+                        extension,
+                        existing,
+                        format: *format,
+                    }
+                );
+            }
+            custom.insert(extension.clone(), *format);
+        }
+        Ok(Self {
+            custom,
+            overrides: Vec::new(),
+        })
+    }
+
+    /// Adds globs of paths to parse as a format, taking precedence over any extension.
+    pub fn with_overrides(mut self, overrides: &[FormatOverride]) -> Self {
+        self.overrides.extend_from_slice(overrides);
+        self
+    }
+
+    /// Detects the format of the file at the given path.
+    ///
+    /// The first format override whose glob matches the path wins, unless the file is one of
+    /// checkonaut's own (see [`is_own_file`]), so broad globs like `**/*` don't pick those up.
+    /// Otherwise, the format is detected by the extension: first the built-in ones, then the custom
+    /// ones.
+    pub fn format_of(&self, path: &Path) -> Option<DataFormat> {
+        let name_bytes = path.file_name()?.as_encoded_bytes();
+        let mut overrides = self.overrides.iter().filter(|_| !is_own_file(name_bytes));
+        if let Some(o) = overrides.find(|o| o.matches(path)) {
+            return Some(o.format);
+        }
+        self.format_of_byte_name(name_bytes)
+    }
+
+    fn format_of_byte_name(&self, name_bytes: &[u8]) -> Option<DataFormat> {
+        let name_bytes = strip_gzip_extension(name_bytes).unwrap_or(name_bytes);
+        let ext = extension_of(name_bytes)?;
+        DataFormat::from_extension(ext).or_else(|| {
+            let ext = std::str::from_utf8(ext).ok()?.to_ascii_lowercase();
+            self.custom.get(&ext).copied()
+        })
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum DataExtensionError {
+    #[snafu(display(
+        "Extension '{extension}' can't be parsed as {format}, as it is already parsed as {existing}"
+    ))]
+    ConflictingDataExtension {
+        extension: String,
+        existing: DataFormat,
+        format: DataFormat,
+    },
+}
@@ -0,0 +1,135 @@
+use crate::{file::is_gzip, lua::CheckError};
+use eyre::{Context, Result, ensure};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// A text replacement in a data file, as suggested by a finding.
+///
+/// A finding's fix can be applied if it is a table of `replace` and `with`, and the finding has a
+/// `line` (and optionally a `column`). The first occurrence of `replace` on that line, at or after
+/// the column, is replaced by `with`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub data_file: PathBuf,
+    line: usize,
+    column: usize,
+    replace: String,
+    with: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Replacement {
+    replace: String,
+    with: String,
+}
+
+impl Fix {
+    /// The fix suggested by the finding, if it can be applied.
+    pub fn from_finding(data_file: &Path, finding: &CheckError) -> Option<Self> {
+        let replacement: Replacement = serde_json::from_str(finding.fix.as_deref()?).ok()?;
+        let location = finding.location.as_ref()?;
+        // Compressed files can't be edited in place, and documents other than the first don't
+        // know where in the file they are.
+        if is_gzip(data_file) || finding.document_index.is_some_and(|i| i > 0) {
+            return None;
+        }
+        Some(Self {
+            data_file: data_file.to_path_buf(),
+            line: location.line?,
+            column: location.column.unwrap_or(1),
+            replace: replacement.replace,
+            with: replacement.with,
+        })
+    }
+
+    /// The byte range in the contents that this fix replaces, if the text to replace is there.
+    fn range(&self, contents: &str) -> Option<(usize, usize)> {
+        let line_start = match self.line.checked_sub(1)? {
+            0 => 0,
+            n => contents.match_indices('\n').nth(n - 1)?.0 + 1,
+        };
+        let line = contents[line_start..].split('\n').next()?;
+        let (column, _) = line.char_indices().nth(self.column.checked_sub(1)?)?;
+        let start = line_start + column + line[column..].find(&self.replace)?;
+        Some((start, start + self.replace.len()))
+    }
+}
+
+/// Applies the fixes to their data files, backing up every changed file with an `.orig` suffix.
+///
+/// Fixes that overlap an earlier fix in the same file, or whose text to replace isn't found, are
+/// skipped. Returns the number of fixes applied.
+pub fn apply(fixes: Vec<Fix>) -> Result<usize> {
+    let mut by_file = BTreeMap::<_, Vec<_>>::new();
+    for fix in fixes {
+        by_file.entry(fix.data_file.clone()).or_default().push(fix);
+    }
+
+    // Every file is fixed in memory first, so that nothing is written if any backup exists.
+    let mut fixed_files = Vec::new();
+    for (path, fixes) in by_file {
+        let contents = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read data file to fix: {}", path.display()))?;
+        let mut ranges = Vec::<(usize, usize, &Fix)>::new();
+        for fix in &fixes {
+            let Some((start, end)) = fix.range(&contents) else {
+                warn!(
+                    path = %path.display(),
+                    line = fix.line,
+                    replace = fix.replace,
+                    "text to fix was not found; skipping fix",
+                );
+                continue;
+            };
+            if ranges.iter().any(|&(s, e, _)| start < e && s < end) {
+                warn!(
+                    path = %path.display(),
+                    line = fix.line,
+                    "fix overlaps another fix; skipping fix",
+                );
+                continue;
+            }
+            ranges.push((start, end, fix));
+        }
+        if ranges.is_empty() {
+            continue;
+        }
+
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".orig");
+        let backup = PathBuf::from(backup);
+        ensure!(
+            !backup.exists(),
+            "backup file already exists, refusing to fix: {}",
+            backup.display()
+        );
+        // Apply from the end, so the earlier ranges stay valid.
+        ranges.sort_unstable_by_key(|&(start, _, _)| std::cmp::Reverse(start));
+        let mut fixed = contents;
+        for &(start, end, fix) in &ranges {
+            fixed.replace_range(start..end, &fix.with);
+        }
+        fixed_files.push((path, backup, fixed, ranges.len()));
+    }
+
+    let mut applied = 0;
+    for (path, backup, fixed, fixes) in fixed_files {
+        std::fs::copy(&path, &backup)
+            .wrap_err_with(|| format!("failed to back up data file: {}", path.display()))?;
+        std::fs::write(&path, fixed)
+            .wrap_err_with(|| format!("failed to write fixed data file: {}", path.display()))?;
+        info!(
+            path = %path.display(),
+            backup = %backup.display(),
+            fixes,
+            "fixed data file",
+        );
+        applied += fixes;
+    }
+    Ok(applied)
+}
@@ -1,24 +1,68 @@
-use clap::{Parser, Subcommand};
+use clap::{ColorChoice, Parser, Subcommand};
 use eyre::{Context, Result};
+use std::{
+    fmt,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
+mod baseline;
+mod cache;
 mod check;
+mod config;
+mod explain;
 mod file;
+mod fix;
+mod lint;
 mod lua;
+mod remote;
 mod test;
 
 /// A tool for running checks against arbitrary JSON-like data.
 #[derive(Debug, Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit codes: 0 if everything passed, 1 if checks or tests found problems, 2 if the run itself failed, 130 if interrupted."
+)]
 pub struct Cli {
     /// The logger configuration.
     #[arg(long, env = "RUST_LOG")]
     pub logger: Option<String>,
 
+    /// Log more: once to show each file as it's checked, twice for everything.
+    ///
+    /// This is ignored if `--logger` or `RUST_LOG` is set, as those configure the logger fully.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// When to colour the output.
+    ///
+    /// With `auto`, the logger decides by itself, e.g. honouring `NO_COLOR`.
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
     /// How many threads should Rayon use in processing?
     /// By default, this is the same amount as CPUs available.
     #[arg(long)]
     rayon_threads: Option<usize>,
 
+    /// The configuration file to read defaults for flags from.
+    ///
+    /// By default, this is the nearest `checkonaut.toml` in the current directory or any of its
+    /// parents, if there is one. Flags given on the command line take precedence.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Stops `check` and `test` runs once cancelled; see [`Cli::cancellation`].
+    #[arg(skip)]
+    cancellation: Option<CancellationToken>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -26,13 +70,47 @@ pub struct Cli {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Check that the given data conforms to the specified checks.
-    Check(self::check::Check),
+    Check(Box<self::check::Check>),
 
     /// Check that the given checks behave as expected against test cases.
-    Test(self::test::Test),
+    Test(Box<self::test::Test>),
+
+    /// List the functions available to checks and tests, with their signatures.
+    Explain(self::explain::Explain),
+
+    /// Check that the check files load, and that their check functions accept an empty document.
+    Lint(Box<self::lint::Lint>),
 }
 
 impl Cli {
+    /// The logger configuration to use: `--logger` (or `RUST_LOG`) if given, and otherwise a
+    /// default based on `--verbose`.
+    pub fn log_filter(&self) -> &str {
+        match (&self.logger, self.verbose) {
+            (Some(logger), _) => logger,
+            (None, 0) => "warn,checkonaut=info",
+            (None, 1) => "warn,checkonaut=debug",
+            (None, _) => "debug,checkonaut=trace",
+        }
+    }
+
+    /// Whether the logger should colour its output: `None` with `--color auto`, leaving it up to
+    /// the logger.
+    pub fn ansi(&self) -> Option<bool> {
+        match self.color {
+            ColorChoice::Auto => None,
+            ColorChoice::Always => Some(true),
+            ColorChoice::Never => Some(false),
+        }
+    }
+
+    /// Makes `check` and `test` runs stop once the token is cancelled, failing with
+    /// [`CancelledError`].
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
     pub fn run(self) -> Result<()> {
         if let Some(n) = self.rayon_threads {
             rayon::ThreadPoolBuilder::new()
@@ -41,21 +119,97 @@ impl Cli {
                 .wrap_err("failed to set up Rayon thread pool")?;
         }
 
+        let config = config::Config::load(self.config.as_deref())?;
         match self.command {
-            Command::Check(cmd) => cmd.run()?,
-            Command::Test(cmd) => cmd.run()?,
+            Command::Check(mut cmd) => {
+                cmd.apply_config(&config)?;
+                cmd.lua.cancellation = self.cancellation;
+                cmd.run()?
+            }
+            Command::Test(mut cmd) => {
+                cmd.apply_config(&config)?;
+                cmd.lua.cancellation = self.cancellation;
+                cmd.run()?
+            }
+            Command::Explain(cmd) => cmd.run()?,
+            Command::Lint(mut cmd) => {
+                cmd.apply_config(&config)?;
+                cmd.run()?
+            }
         }
 
         Ok(())
     }
 }
 
+/// The error of a run that completed, but found problems: findings at or above the fail-on
+/// threshold, or failing tests.
+///
+/// Any other error means that the run itself failed, e.g. due to an IO error.
+#[derive(Debug)]
+pub struct FindingsError(String);
+
+impl fmt::Display for FindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FindingsError {}
+
+/// The error of a run that stopped early, because it was cancelled with a [`CancellationToken`].
+#[derive(Debug)]
+pub struct CancelledError;
+
+impl fmt::Display for CancelledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("interrupted")
+    }
+}
+
+impl std::error::Error for CancelledError {}
+
+/// A handle to cancel a run with, e.g. on Ctrl-C: no more files are checked, and running Lua code
+/// is aborted. Clones share the same cancellation.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Cancels the run. Returns whether it had already been cancelled.
+    pub fn cancel(&self) -> bool {
+        self.0.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The process exit code for the result of [`Cli::run`].
+///
+/// This is 0 if the run passed, 1 if it found problems (see [`FindingsError`]), 130 if it was
+/// cancelled (see [`CancelledError`]), or 2 if the run itself failed. The latter matches the exit
+/// code for invalid arguments.
+pub fn exit_code(result: &Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is::<FindingsError>() => ExitCode::from(1),
+        Err(e) if e.is::<CancelledError>() => ExitCode::from(130),
+        Err(_) => ExitCode::from(2),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Cli;
+    use crate::{
+        check::{github_annotation, is_relevant_change, plain_line},
+        file::{DataExtensions, FileSearchResult, FileSearcher, retry_io},
+        lua::{CheckError, CheckSeverity, Location, LuaOptions, SourceCode},
+    };
     use clap::Parser;
     use eyre::{Context, ContextCompat, Result};
-    use std::fs;
+    use std::{fs, path::Path, process::ExitCode, time::Duration};
     use test_temp_dir::test_temp_dir;
     use tracing_test::traced_test;
 
@@ -148,6 +302,81 @@ mod tests {
         let formatted = format!("{res:?}");
         assert!(formatted.contains("runtime error:"));
         assert!(formatted.contains("shouldn't happen :("));
+        // The offending line of the check is shown, too.
+        assert!(
+            formatted.contains(r#"3 | error("shouldn't happen :( but some people might do this")"#),
+            "missing source snippet: {formatted}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_syntax_error_snippet() -> Result<()> {
+        const SCRIPT: &str = "function Check()\n    local x = 1\n    x = x 2\nend\n";
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected a syntax error");
+        let formatted = format!("{err:#}");
+        assert!(formatted.contains("syntax error"), "{formatted}");
+        assert!(
+            formatted.contains("3 | x = x 2\n  |       ^"),
+            "missing source snippet: {formatted}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_skip_broken_checks() -> Result<()> {
+        let dir = test_temp_dir!();
+        fs::write(
+            dir.as_path_untracked().join("broken.lua"),
+            "function Check(\n",
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("working.lua"),
+            "function Check() return 'working check ran' end",
+        )?;
+        fs::write(dir.as_path_untracked().join("data.json"), "{}")?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .expect_err("expected the broken check to fail the run");
+        let formatted = format!("{err:#}");
+        assert!(
+            formatted.contains("failed to load Lua source from")
+                && formatted.contains("broken.lua"),
+            "{formatted}"
+        );
+        assert!(!logs_contain("working check ran"));
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--skip-broken-checks", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the working check to report");
+        assert!(logs_contain("check file failed to load; skipping it"));
+        assert!(logs_contain("working check ran"));
 
         Ok(())
     }
@@ -266,4 +495,4971 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_check_progress() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return "found " .. obj.name
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        for name in ["a", "b", "c"] {
+            fs::write(
+                dir.as_path_untracked().join(format!("{name}.json")),
+                format!(r#"{{"name": "{name}"}}"#),
+            )?;
+        }
+        let report = dir.as_path_untracked().join("report.txt");
+        let output = dir.as_path_untracked().join("output.txt");
+
+        // The bar is hidden when stderr isn't a terminal, and never mixes with the findings.
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--progress",
+            "--plain",
+            "--output",
+            output.to_str().wrap_err("non UTF-8 test dir")?,
+            "--report",
+            report.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the findings to fail the run");
+
+        let report = fs::read_to_string(&report)?;
+        assert!(
+            report.contains("3 files with 1 check: 3 errors"),
+            "{report}"
+        );
+        let output = fs::read_to_string(&output)?;
+        assert_eq!(output.lines().count(), 3, "{output}");
+        assert!(!output.contains("files checked"), "{output}");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_fail_on_threshold() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { message = "just a warning", severity = "warning" }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let path = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", path])
+            .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("warnings should not fail with the default threshold")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--fail-on", "warning", "--", path])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected warning to fail the run");
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--fail-on", "none", "--", path])
+            .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("nothing should fail with no threshold")?;
+        assert!(
+            !logs_contain("no errors found"),
+            "findings that don't fail the run are still findings"
+        );
+
+        fs::write(
+            dir.as_path_untracked().join("script.lua"),
+            "function Check() end",
+        )?;
+        let cmd = Cli::try_parse_from(["unittest", "check", "--fail-on", "none", "--", path])
+            .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("expected no findings")?;
+        assert!(logs_contain("no errors found"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_reports_location() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return {
+                    message = "bad value",
+                    line = 3,
+                    column = 5,
+                    path = "/foo",
+                }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("bad value (at path /foo, line 3, column 5)"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_reports_document_index() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "document is bad"
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.yaml"),
+            "bad: false\n---\nbad: false\n---\nbad: true\n",
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("document is bad (document #2)"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_json_array_as_documents() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "element is bad"
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("array.json"),
+            r#"[{"bad": false}, {"bad": true}]"#,
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("object.json"),
+            r#"{"bad": false}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("arrays should be a single document by default")?;
+
+        let cmd =
+            Cli::try_parse_from(["unittest", "check", "--json-array-as-documents", "--", dir])
+                .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("element is bad (document #1)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_query() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+            function Check(obj)
+                local single = checkonaut.Query(obj, "$.spec.name")
+                if single ~= "web" then
+                    error("unexpected single match: " .. tostring(single))
+                end
+                local many = checkonaut.Query(obj, "$.spec.containers[*].image")
+                if #many ~= 2 or many[1] ~= "nginx" or many[2] ~= "redis" then
+                    error("unexpected multiple matches")
+                end
+                if checkonaut.Query(obj, "$.missing") ~= nil then
+                    error("expected nil for no matches")
+                end
+                if pcall(checkonaut.Query, obj, "not a path") then
+                    error("expected invalid path to error")
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"spec": {"name": "web", "containers": [{"image": "nginx"}, {"image": "redis"}]}}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run check")?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_quiet() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--quiet",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run check")?;
+        assert!(!logs_contain("no errors found"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_validate_schema() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+            function Check(obj)
+                return checkonaut.ValidateSchema(obj, "schema.json")
+            end
+        "#;
+        const SCHEMA: &str = r#"{
+            "type": "object",
+            "properties": { "replicas": { "type": "integer" } },
+            "required": ["name"]
+        }"#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("schema.json"), SCHEMA)?;
+        fs::write(
+            dir.as_path_untracked().join("data.yaml"),
+            "name: valid\nreplicas: 3\n---\nreplicas: three\n",
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain(
+            "\\\"name\\\" is a required property (document #1)"
+        ));
+        assert!(logs_contain("/replicas: \\\"three\\\" is not of type"));
+        assert!(!logs_contain("document #0"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_memory_limit() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                local hog = { }
+                for i = 1, 10000000 do
+                    hog[i] = string.rep("x", 64) .. i
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--memory-limit",
+            "1000000",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        let formatted = format!("{res:?}");
+        assert!(formatted.contains("exceeded the Lua memory limit"));
+        assert!(formatted.contains("script.lua"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_custom_function_name() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return "from Check"
+            end
+            function ValidateSecurity()
+                return "from ValidateSecurity"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--check-fn",
+            "ValidateSecurity",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("from ValidateSecurity"));
+        assert!(!logs_contain("from Check"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_honours_ignore_file() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "document is bad"
+                end
+                return nil
+            end
+        "#;
+        const IGNORE: &str = "# Comments are skipped.\n\nvendor\nbroken-*.json\n";
+        let dir = test_temp_dir!();
+        let root = dir.as_path_untracked();
+        fs::create_dir_all(root.join("data/vendor"))?;
+        fs::write(root.join("script.lua"), SCRIPT)?;
+        fs::write(root.join("data/.checkonautignore"), IGNORE)?;
+        fs::write(root.join("data/good.json"), r#"{"bad": false}"#)?;
+        fs::write(root.join("data/broken-1.json"), r#"{"bad": true}"#)?;
+        fs::write(root.join("data/vendor/data.json"), r#"{"bad": true}"#)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            root.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("ignored files should not be checked")?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_xml() -> Result<()> {
+        const SCRIPT: &str = r##"
+            function Check(obj)
+                local issues = { }
+                local service = obj.service
+                if service["@name"] ~= "billing" then
+                    table.insert(issues, "unexpected name")
+                end
+                if #service.endpoint ~= 2 or service.endpoint[2]["#text"] ~= "b & c" then
+                    table.insert(issues, "unexpected endpoints")
+                end
+                if service.owner ~= "team" or service.empty == nil then
+                    table.insert(issues, "unexpected children")
+                end
+                if #issues == 0 then
+                    table.insert(issues, "xml looks right")
+                end
+                return issues
+            end
+        "##;
+        const DATA: &str = r#"<?xml version="1.0"?>
+            <service name="billing">
+                <endpoint port="1">a</endpoint>
+                <endpoint port="2">b &amp; c</endpoint>
+                <owner>team</owner>
+                <empty/>
+            </service>
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("data.xml"), DATA)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("xml looks right"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_hcl() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                local buckets = obj.resource.aws_s3_bucket
+                if #buckets.logs == 2 and buckets.logs[2].bucket == "logs-2" and buckets.data.tags.team == "data" then
+                    return "hcl looks right"
+                end
+            end
+        "#;
+        const DATA: &str = r#"
+            resource "aws_s3_bucket" "logs" {
+                bucket = "logs-1"
+            }
+            resource "aws_s3_bucket" "logs" {
+                bucket = "logs-2"
+            }
+            resource "aws_s3_bucket" "data" {
+                bucket = "data"
+                tags = { team = "data" }
+            }
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("main.tf"), DATA)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("hcl looks right"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_json_stream() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return "saw value " .. obj.n
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.jsons"),
+            r#"{"n": 1}{"n": 2} {"n": 3}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("saw value 1 (document #0)"));
+        assert!(logs_contain("saw value 3 (document #2)"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_csv_rows() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(row)
+                if row.owner == "" then
+                    return "missing owner for " .. row.name
+                end
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.csv"),
+            "name,owner\nbilling,team\nsearch,\n",
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("missing owner for search (document #1)"));
+        assert!(!logs_contain("missing owner for billing"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_tsv_malformed_record() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.tsv"),
+            "name\towner\nbilling\tteam\nsearch\n",
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected error but got success");
+        assert!(
+            format!("{err:#}").contains("failed to parse TSV record 2"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_list_checks() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                error("checks should not run when listing them")
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        fs::write(
+            dir.as_path_untracked().join("library.lua"),
+            "return { helper = function() end }",
+        )?;
+        fs::write(
+            dir.as_path_untracked().join(".hidden.lua"),
+            "function Check() end",
+        )?;
+        let output = dir.as_path_untracked().join("checks.txt");
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--list-checks",
+            "--output",
+            output.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to list checks")?;
+
+        // Only files with a check function are listed, and dotfiles only with `--dotfiles`.
+        let listed = fs::read_to_string(&output)?;
+        let script = dir.as_path_untracked().join("script.lua");
+        assert_eq!(listed, format!("{}\n", script.display()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_discovery_loads_source_once() -> Result<()> {
+        let dir = test_temp_dir!();
+        let counter = dir.as_path_untracked().join("loads.txt");
+        let script = format!(
+            r#"
+            local f = assert(io.open({:?}, "a"))
+            f:write("loaded\n")
+            f:close()
+            function Check(obj)
+                local f = assert(io.open({:?}, "a"))
+                f:write("checked " .. obj.name .. "\n")
+                f:close()
+            end
+        "#,
+            counter.to_str().wrap_err("non UTF-8 test dir")?,
+            counter.to_str().wrap_err("non UTF-8 test dir")?,
+        );
+        fs::write(dir.as_path_untracked().join("script.lua"), script)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"name": "foo"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--allow-unsafe",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run checks")?;
+        assert_eq!(fs::read_to_string(&counter)?, "loaded\nchecked foo\n");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_functions_do_not_leak_between_checks() -> Result<()> {
+        const FIRST: &str = r#"
+            function Check_Extra()
+                return "found by the first check"
+            end
+        "#;
+        const SECOND: &str = r#"
+            function Check()
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("a.lua"), FIRST)?;
+        fs::write(dir.as_path_untracked().join("b.lua"), SECOND)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the first check to fail");
+        // The second check shares the Lua state, but must not run the first check's function.
+        assert!(logs_contain(
+            "checked 1 file with 2 checks: 1 error, 0 warnings"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_dump_value() -> Result<()> {
+        let dir = test_temp_dir!();
+        let data = dir.as_path_untracked().join("data.yaml");
+        fs::write(&data, "foo: bar\n---\nbaz: [1, 2]\n")?;
+        let broken = dir.as_path_untracked().join("broken.json");
+        fs::write(&broken, "{")?;
+
+        // There are no check files, so this would fail if we tried to run any checks.
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--dump-value",
+            data.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to dump data file")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--dump-value",
+            broken.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected the broken file to fail");
+        assert!(
+            format!("{err:#}").contains("failed to parse JSON"),
+            "unexpected error: {err:#}"
+        );
+
+        // Documents given with `--data` are read like they would be for checking.
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--data",
+            "foo: [1, 2]",
+            "--data-format",
+            "yaml",
+            "--dump-value",
+            "<inline>",
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to dump inline data")?;
+
+        let missing = dir.as_path_untracked().join("missing.json");
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--read-retries",
+            "2",
+            "--dump-value",
+            missing.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected the missing file to fail");
+        assert!(
+            format!("{err:#}").contains("failed to read data file"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_timings() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        for i in 0..3 {
+            fs::write(
+                dir.as_path_untracked().join(format!("data{i}.json")),
+                r#"{"foo": "bar"}"#,
+            )?;
+        }
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--timings",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run checks with timings")?;
+
+        assert!(logs_contain("runs  check"));
+        assert!(logs_contain(&format!(
+            "     3  {}",
+            dir.as_path_untracked().join("script.lua").display()
+        )));
+        assert!(logs_contain("data file"));
+        for i in 0..3 {
+            assert!(logs_contain(&format!("data{i}.json")));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtins_documented() -> Result<()> {
+        let dir = test_temp_dir!();
+        let path = dir.as_path_untracked().join("script_test.lua");
+        fs::write(&path, "")?;
+        let keys = |with_test_module: bool, module: &str| -> Result<Vec<String>> {
+            let lua = LuaOptions::default().new_lua()?;
+            let source = SourceCode::read(&path)?;
+            if with_test_module {
+                source.load_test_into(&lua)?;
+            } else {
+                source.load_into(&lua)?;
+            }
+            let table: mlua::Table = lua
+                .load(format!("return require({module:?})"))
+                .eval()
+                .map_err(|e| eyre::eyre!("failed to require {module}: {e}"))?;
+            let mut keys = (table.pairs::<String, mlua::Value>())
+                .map(|pair| pair.map(|(k, _)| k))
+                .collect::<mlua::Result<Vec<_>>>()
+                .map_err(|e| eyre::eyre!("failed to list {module}: {e}"))?;
+            keys.sort_unstable();
+            Ok(keys)
+        };
+
+        // Everything registered in the modules is documented for `explain`, and vice versa.
+        for (with_test_module, module, builtins) in [
+            (false, "@checkonaut", SourceCode::BUILTINS),
+            (true, "@checkonaut.test", SourceCode::TEST_BUILTINS),
+        ] {
+            let mut documented = builtins.iter().map(|b| b.name).collect::<Vec<_>>();
+            documented.sort_unstable();
+            documented.dedup();
+            assert_eq!(
+                documented.len(),
+                builtins.len(),
+                "duplicate builtins in {module}"
+            );
+            assert_eq!(keys(with_test_module, module)?, documented, "{module}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let cmd = Cli::try_parse_from(["unittest", "explain"]).wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to explain")?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_warn_unused_checks() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return nil
+            end
+        "#;
+        const TYPO: &str = r#"
+            function Chekc()
+                return "never run"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("typo.lua"), TYPO)?;
+        fs::write(dir.as_path_untracked().join("typo_test.lua"), "")?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let path = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", path])
+            .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run checks")?;
+        assert!(!logs_contain("does not define a check function"));
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--warn-unused-checks",
+            "--no-ignore-tests",
+            "--",
+            path,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run checks")?;
+        assert!(logs_contain("does not define a check function"));
+        assert!(logs_contain("typo.lua"));
+        assert!(!logs_contain("typo_test.lua"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_list_data() -> Result<()> {
+        let dir = test_temp_dir!();
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        fs::write(dir.as_path_untracked().join("data.yaml"), "foo: bar\n")?;
+
+        fs::write(dir.as_path_untracked().join("other.yaml"), "foo: baz\n")?;
+        fs::write(dir.as_path_untracked().join(".hidden.json"), "{}")?;
+        let output = dir.as_path_untracked().join("data.txt");
+
+        // We have no check files, so this would fail if we tried to run any checks.
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--list-data",
+            "--output",
+            output.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to list data")?;
+
+        let listed = fs::read_to_string(&output)?;
+        let path = |name: &str| dir.as_path_untracked().join(name).display().to_string();
+        assert_eq!(
+            listed,
+            format!(
+                "JSON:\n  {}\nYAML:\n  {}\n  {}\n",
+                path("data.json"),
+                path("data.yaml"),
+                path("other.yaml"),
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_set_default_severity() -> Result<()> {
+        const MIGRATING_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+            checkonaut.SetDefaultSeverity("warning")
+            function Check()
+                return { "still migrating", { message = "explicit error", severity = "error" } }
+            end
+        "#;
+        const SCRIPT: &str = r#"
+            function Check()
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("a.lua"), MIGRATING_SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("b.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("[Warning] still migrating"));
+        assert!(logs_contain("[Error] explicit error"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_glob_inputs() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "document is bad"
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let root = dir.as_path_untracked();
+        fs::create_dir_all(root.join("configs/nested"))?;
+        fs::write(root.join("script.lua"), SCRIPT)?;
+        fs::write(root.join("configs/nested/good.yaml"), "bad: false\n")?;
+        fs::write(root.join("configs/bad.json"), r#"{"bad": true}"#)?;
+        let root = root.to_str().wrap_err("non UTF-8 test dir")?;
+        let script = format!("{root}/script.lua");
+
+        let good = format!("{root}/configs/**/*.yaml");
+        let cmd = Cli::try_parse_from(["unittest", "check", "-c", &script, "--", &good])
+            .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("only the good file should be checked")?;
+
+        let bad = format!("{root}/configs/*.json");
+        let cmd = Cli::try_parse_from(["unittest", "check", "-c", &script, "--", &bad])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "the bad file should be checked");
+
+        let none = format!("{root}/configs/*.toml");
+        let cmd = Cli::try_parse_from(["unittest", "check", "-c", &script, "--", &none])
+            .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(
+            format!("{res:?}").contains("did not match any files"),
+            "expected glob error, got {res:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_no_ignore_tests() -> Result<()> {
+        const TEST_SCRIPT: &str = r#"
+            function Check()
+                return "from a test file"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let path = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", path])
+            .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(
+            format!("{res:?}").contains("no check files found"),
+            "test files should be ignored by default"
+        );
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--no-ignore-tests", "--", path])
+            .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("from a test file"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_summary() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return { "an error", { message = "a warning", severity = "warning" } }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("a.json"), r#"{"foo": "bar"}"#)?;
+        fs::write(dir.as_path_untracked().join("b.json"), r#"{"foo": "bar"}"#)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--quiet",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain(
+            "checked 2 files with 1 check: 2 errors, 2 warnings"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_helpers() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestSplit()
+                local parts = checkonaut.Split("a,b,,c", ",")
+                assert(#parts == 4 and parts[1] == "a" and parts[3] == "" and parts[4] == "c")
+                local chars = checkonaut.Split("abc", "")
+                assert(#chars == 3 and chars[2] == "b")
+                assert(#checkonaut.Split("", "") == 0)
+                local empty = checkonaut.Split("", ",")
+                assert(#empty == 1 and empty[1] == "")
+            end
+
+            function TestTrimSpace()
+                assert(checkonaut.TrimSpace("  \t padded \n") == "padded")
+                assert(checkonaut.TrimSpace("") == "")
+            end
+
+            function TestPrefixSuffix()
+                assert(checkonaut.StartsWith("kube-system", "kube-"))
+                assert(not checkonaut.StartsWith("default", "kube-"))
+                assert(checkonaut.StartsWith("anything", ""))
+                assert(checkonaut.EndsWith("image:latest", ":latest"))
+                assert(not checkonaut.EndsWith("", "x"))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_helpers() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestNow()
+                assert(checkonaut.Now() > 1700000000)
+            end
+
+            function TestParseDate()
+                assert(checkonaut.ParseDate("2024-01-02T03:04:05Z") == 1704164645)
+                assert(checkonaut.ParseDate("2024-01-02", "%Y-%m-%d") == 1704153600)
+                assert(checkonaut.ParseDate("2024-01-02 03:04:05", "%Y-%m-%d %H:%M:%S") == 1704164645)
+                assert(checkonaut.ParseDate("not a date", "%Y-%m-%d") == nil)
+                assert(checkonaut.ParseDate("not a date") == nil)
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deep_equal_helpers() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestDeepEqual()
+                assert(checkonaut.DeepEqual({ a = { 1, 2 }, b = "x" }, { b = "x", a = { 1, 2 } }))
+                assert(not checkonaut.DeepEqual({ a = { 1, 2 } }, { a = { 2, 1 } }))
+                assert(checkonaut.DeepEqual(nil, nil))
+                assert(checkonaut.DeepEqual("x", "x"))
+                assert(not checkonaut.DeepEqual(1, 1.0))
+            end
+
+            function TestContains()
+                assert(checkonaut.Contains({ { name = "a" }, { name = "b" } }, { name = "b" }))
+                assert(not checkonaut.Contains({ "a", "b" }, "c"))
+                assert(not checkonaut.Contains({ }, "c"))
+                assert(not pcall(checkonaut.Contains, { a = "b" }, "b"))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestNested()
+                local flat = checkonaut.Flatten({ a = { b = { c = 1 } }, d = "e" })
+                assert(checkonaut.DeepEqual(flat, { ["a.b.c"] = 1, d = "e" }))
+            end
+
+            function TestNestedArrays()
+                local flat = checkonaut.Flatten({
+                    list = { { name = "x" }, "y" },
+                    matrix = { { 1, 2 }, { { 3 } } },
+                })
+                assert(checkonaut.DeepEqual(flat, {
+                    ["list.0.name"] = "x",
+                    ["list.1"] = "y",
+                    ["matrix.0.0"] = 1,
+                    ["matrix.0.1"] = 2,
+                    ["matrix.1.0.0"] = 3,
+                }))
+                assert(checkonaut.DeepEqual(checkonaut.Flatten({ "a", { "b" } }), {
+                    ["0"] = "a",
+                    ["1.0"] = "b",
+                }))
+            end
+
+            function TestDottedKeys()
+                local flat = checkonaut.Flatten({
+                    ["dotted.key"] = { ["back\\slash"] = true, ["a.b.c"] = 1 },
+                })
+                assert(checkonaut.DeepEqual(flat, {
+                    ["dotted\\.key.back\\\\slash"] = true,
+                    ["dotted\\.key.a\\.b\\.c"] = 1,
+                }))
+            end
+
+            function TestEmptyTable()
+                assert(checkonaut.DeepEqual(checkonaut.Flatten({ }), { }))
+                local flat = checkonaut.Flatten({ empty = { }, nested = { empty = { } } })
+                assert(checkonaut.DeepEqual(flat, { empty = { }, ["nested.empty"] = { } }))
+            end
+
+            function TestNotATable()
+                assert(not pcall(checkonaut.Flatten, "nope"))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_values() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestKeys()
+                local obj = { zeta = 1, alpha = { 2 }, mid = "three", [1] = "skipped" }
+                assert(checkonaut.DeepEqual(checkonaut.Keys(obj), { "alpha", "mid", "zeta" }))
+                assert(checkonaut.DeepEqual(checkonaut.Keys({ }), { }))
+                assert(checkonaut.DeepEqual(checkonaut.Keys({ "only", "indices" }), { }))
+            end
+
+            function TestValues()
+                local obj = { zeta = 1, alpha = { 2 }, mid = "three", [1] = "skipped" }
+                assert(checkonaut.DeepEqual(checkonaut.Values(obj), { { 2 }, "three", 1 }))
+                assert(checkonaut.DeepEqual(checkonaut.Values({ }), { }))
+            end
+
+            function TestNotATable()
+                local ok, err = pcall(checkonaut.Keys, "nope")
+                assert(not ok and tostring(err):find("Keys expects a table, got string"))
+                ok, err = pcall(checkonaut.Values, nil)
+                assert(not ok and tostring(err):find("Values expects a table, got nil"))
+                ok, err = pcall(checkonaut.Values, true)
+                assert(not ok and tostring(err):find("Values expects a table, got boolean"))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            function TestSeed()
+                local first = math.random(1, 1 << 40)
+                math.randomseed(42)
+                assert(first == math.random(1, 1 << 40))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--seed",
+            "42",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestEnv()
+                assert(checkonaut.Env("PATH") ~= nil)
+                assert(checkonaut.Env("HOME") == nil)
+                assert(checkonaut.Env("CHECKONAUT_TEST_UNSET") == nil)
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--expose-env",
+            "PATH",
+            "--expose-env",
+            "CHECKONAUT_TEST_UNSET",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_base64() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestBase64()
+                assert(checkonaut.Base64Encode("hello") == "aGVsbG8=")
+                assert(checkonaut.Base64Decode("aGVsbG8=") == "hello")
+                assert(checkonaut.Base64Encode("\x02??") == "Aj8/")
+                assert(checkonaut.Base64Encode("\x02??", true) == "Aj8_")
+                assert(checkonaut.Base64Decode("Aj8_", true) == "\x02??")
+                assert(not pcall(checkonaut.Base64Decode, "Aj8_"))
+                assert(not pcall(checkonaut.Base64Decode, "not base64!"))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_file() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestReadFile()
+                assert(checkonaut.ReadFile("sibling.txt") == "  raw\n\xff")
+                assert(checkonaut.ReadFile("sibling.txt", 7) == "  raw\n\xff")
+                local ok, err = pcall(checkonaut.ReadFile, "sibling.txt", 6)
+                assert(not ok)
+                assert(string.find(tostring(err), "more than the limit of 6 bytes", 1, true))
+                ok, err = pcall(checkonaut.ReadFile, "missing.txt")
+                assert(not ok)
+                assert(string.find(tostring(err), "missing.txt", 1, true))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("sibling.txt"), b"  raw\n\xff")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestMatchesString()
+                assert(checkonaut.Matches("foo-123", "^foo-[0-9]+$"))
+                assert(not checkonaut.Matches("bar", "^foo"))
+                local ok, err = pcall(checkonaut.Matches, "foo", "(")
+                assert(not ok)
+                assert(string.find(tostring(err), "invalid regex pattern '('", 1, true))
+            end
+
+            function TestMatchesAny()
+                assert(checkonaut.Matches("bar", { "^foo", "^bar" }))
+                assert(not checkonaut.Matches("baz", { "^foo", "^bar" }))
+                assert(not checkonaut.Matches("baz", { }))
+                local ok, err = pcall(checkonaut.Matches, "foo", { "^foo", "(" })
+                assert(not ok)
+                assert(string.find(tostring(err), "invalid regex pattern #2 '('", 1, true))
+                ok, err = pcall(checkonaut.Matches, "foo", 1)
+                assert(not ok)
+                assert(string.find(tostring(err), "got integer", 1, true))
+            end
+
+            function TestMatchesManyPatterns()
+                -- More patterns than are cached, so the cache is cleared along the way.
+                for i = 1, 600 do
+                    assert(checkonaut.Matches("id-" .. i, "^id-" .. i .. "$"))
+                    assert(not checkonaut.Matches("id-" .. i, "^id-" .. (i + 1) .. "$"))
+                end
+                assert(checkonaut.Matches("id-1", "^id-1$"))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_flags() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestCaseInsensitive()
+                assert(not checkonaut.Matches("FOO", "^foo$"))
+                assert(checkonaut.Matches("FOO", "^foo$", "i"))
+                assert(checkonaut.Matches("BAR", { "^foo", "^bar" }, "i"))
+            end
+
+            function TestMultiLine()
+                assert(not checkonaut.Matches("a\nfoo\nb", "^foo$"))
+                assert(checkonaut.Matches("a\nfoo\nb", "^foo$", "m"))
+                assert(checkonaut.Matches("a\nFoo", "^foo$", "mi"))
+            end
+
+            function TestDotMatchesNewLine()
+                assert(not checkonaut.Matches("a\nb", "^a.b$"))
+                assert(checkonaut.Matches("a\nb", "^a.b$", "s"))
+            end
+
+            function TestIgnoreWhitespace()
+                assert(not checkonaut.Matches("foo-123", "^ foo - [0-9]+ $"))
+                assert(checkonaut.Matches("foo-123", "^ foo - [0-9]+ $  # a comment", "x"))
+            end
+
+            function TestNoFlags()
+                assert(checkonaut.Matches("foo", "^foo$", ""))
+                assert(checkonaut.Matches("foo", "^foo$", nil))
+            end
+
+            function TestInvalidFlag()
+                local ok, err = pcall(checkonaut.Matches, "foo", "foo", "iq")
+                assert(not ok)
+                assert(string.find(tostring(err), "invalid regex flag 'q'", 1, true))
+                ok, err = pcall(checkonaut.Matches, "foo", "foo", "I")
+                assert(not ok)
+                assert(string.find(tostring(err), "invalid regex flag 'I'", 1, true))
+            end
+
+            function TestCachedByPatternAndFlags()
+                -- The same pattern with other flags must not reuse the compiled pattern, whichever
+                -- was compiled first.
+                assert(checkonaut.Matches("FOO", "^cached$|^foo$", "i"))
+                assert(not checkonaut.Matches("FOO", "^cached$|^foo$"))
+                assert(not checkonaut.Matches("BAR", "^other$|^bar$"))
+                assert(checkonaut.Matches("BAR", "^other$|^bar$", "i"))
+                -- The order of the flags doesn't matter.
+                assert(checkonaut.Matches("a\nFOO", "^foo$", "im"))
+                assert(checkonaut.Matches("a\nFOO", "^foo$", "mi"))
+                assert(not checkonaut.Matches("a\nFOO", "^foo$", "m"))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_jobs() -> Result<()> {
+        let dir = test_temp_dir!();
+        let order = dir.as_path_untracked().join("order.txt");
+        let order_path = order.to_str().wrap_err("non UTF-8 test dir")?;
+        for name in ["c", "a", "b"] {
+            fs::write(
+                dir.as_path_untracked().join(format!("{name}.lua")),
+                "function Check() end",
+            )?;
+            fs::write(
+                dir.as_path_untracked().join(format!("{name}_test.lua")),
+                format!(
+                    r#"
+                    function TestOrder()
+                        local f = assert(io.open({order_path:?}, "a"))
+                        f:write("{name}\n")
+                        f:close()
+                    end
+                "#
+                ),
+            )?;
+        }
+        let path = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd =
+            Cli::try_parse_from(["unittest", "test", "--allow-unsafe", "-j", "1", "--", path])
+                .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+        assert_eq!(fs::read_to_string(&order)?, "a\nb\nc\n");
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--allow-unsafe",
+            "--jobs",
+            "2",
+            "--",
+            path,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+        assert_eq!(fs::read_to_string(&order)?.lines().count(), 6);
+
+        assert!(
+            Cli::try_parse_from(["unittest", "test", "-j", "0", "--", path]).is_err(),
+            "expected 0 jobs to be rejected"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_semver() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestSemverSatisfies()
+                assert(checkonaut.SemverSatisfies("1.4.0", ">=1.2, <2.0"))
+                assert(not checkonaut.SemverSatisfies("2.0.0", ">=1.2, <2.0"))
+                local ok, err = pcall(checkonaut.SemverSatisfies, "1.x", ">=1.2")
+                assert(not ok)
+                assert(string.find(tostring(err), "invalid version '1.x'", 1, true))
+                ok, err = pcall(checkonaut.SemverSatisfies, "1.0.0", "not a requirement")
+                assert(not ok)
+                assert(string.find(tostring(err), "invalid requirement", 1, true))
+            end
+
+            function TestSemverCompare()
+                assert(checkonaut.SemverCompare("1.2.3", "1.10.0") == -1)
+                assert(checkonaut.SemverCompare("1.2.3", "1.2.3") == 0)
+                assert(checkonaut.SemverCompare("1.2.3", "1.2.3-rc.1") == 1)
+                local ok, err = pcall(checkonaut.SemverCompare, "1.2.3", "latest")
+                assert(not ok)
+                assert(string.find(tostring(err), "invalid second version 'latest'", 1, true))
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_filter() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            function TestSelected()
+            end
+
+            function TestBroken()
+                error("filtered out tests should not run")
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "test", "--filter", "Selected", "--", dir])
+            .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to run tests")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "test", "--filter", "Nothing", "--", dir])
+            .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("a filter matching nothing should pass")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "test", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the broken test to fail");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_test_summary() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            function TestOne()
+            end
+
+            function TestTwo()
+            end
+
+            function TestBroken()
+                error("broken")
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the broken test to fail");
+        assert!(logs_contain("ran 3 tests in 1 file: 2 passed, 1 failed"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_test_count_only() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            function TestOne()
+            end
+
+            function TestBroken()
+                error("broken")
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--count-only",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let result = cmd.run();
+        assert_eq!(super::exit_code(&result), ExitCode::from(1));
+        assert!(!logs_contain("test failure"));
+        assert!(logs_contain("ran 2 tests in 1 file: 1 passed, 1 failed"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_assertion_helpers() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local t = require("@checkonaut").test
+            assert(t == require("@checkonaut.test"))
+
+            function TestPassing()
+                t.AssertEqual({ a = { 1, 2 } }, { a = { 1, 2 } })
+                t.AssertContains({ "a", "b" }, "b")
+                t.AssertError(function() error("expected") end)
+            end
+
+            function TestAssertEqual()
+                t.AssertEqual({ name = "actual" }, { name = "expected" })
+            end
+
+            function TestAssertContains()
+                t.AssertContains({ "a", "b" }, "c")
+            end
+
+            function TestAssertError()
+                t.AssertError(function() return "fine" end)
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected the failing assertions to fail"
+        );
+        assert!(logs_contain("ran 4 tests in 1 file: 1 passed, 3 failed"));
+        assert!(logs_contain(
+            r#"AssertEqual failed: expected {"name":"expected"}, got {"name":"actual"}"#
+        ));
+        assert!(logs_contain(
+            r#"AssertContains failed: expected ["a","b"] to contain "c""#
+        ));
+        assert!(logs_contain(
+            r#"AssertError failed: expected an error, but the function returned "fine""#
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_cannot_use_test_module() -> Result<()> {
+        const SCRIPT: &str = r#"
+            assert(require("@checkonaut").test == nil, "checkonaut.test is available to checks")
+            require("@checkonaut.test")
+            function Check()
+                return { }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected the require to fail");
+        assert!(
+            format!("{err:#}").contains("@checkonaut.test"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_now() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { }
+            end
+        "#;
+        const TEST_SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function TestNow()
+                assert(checkonaut.Now() == 1704164645)
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("script_test.lua"), TEST_SCRIPT)?;
+        let path = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        for now in [
+            "1704164645",
+            "2024-01-02T03:04:05Z",
+            "2024-01-02T04:04:05+01:00",
+        ] {
+            let cmd = Cli::try_parse_from(["unittest", "test", "--now", now, "--", path])
+                .wrap_err("failed to parse args")?;
+            cmd.run()
+                .wrap_err_with(|| format!("failed to run tests with --now {now}"))?;
+        }
+
+        let res = Cli::try_parse_from(["unittest", "test", "--now", "yesterday", "--", path]);
+        assert!(res.is_err(), "invalid --now should fail to parse");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_only() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return "from the selected check"
+            end
+        "#;
+        const OTHER_SCRIPT: &str = r#"
+            function Check()
+                return "from another check"
+            end
+        "#;
+        const LIBRARY: &str = r#"
+            function Helper()
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let root = dir.as_path_untracked();
+        fs::write(root.join("script.lua"), SCRIPT)?;
+        fs::write(root.join("other.lua"), OTHER_SCRIPT)?;
+        fs::write(root.join("library.lua"), LIBRARY)?;
+        fs::write(root.join("data.json"), r#"{"foo": "bar"}"#)?;
+        let path = root.to_str().wrap_err("non UTF-8 test dir")?;
+        let script = format!("{path}/script.lua");
+        let library = format!("{path}/library.lua");
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--only", &script, "--", path])
+            .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("from the selected check"));
+        assert!(!logs_contain("from another check"));
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--only", &library, "--", path])
+            .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(
+            format!("{res:?}").contains("does not define a 'Check' function"),
+            "expected missing function error, got {res:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_reports_rule_id() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return {
+                    { message = "has an id", id = "no-latest-tag" },
+                    { message = "has no id" },
+                }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("[Error] [no-latest-tag] has an id"));
+        assert!(logs_contain("[Error] has no id"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_baseline_suppresses_known_findings() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(doc)
+                local errs = { { message = "known problem", id = "known" } }
+                if doc.new then
+                    table.insert(errs, { message = "new problem" })
+                end
+                return errs
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let data_dir = dir.as_path_untracked().join("data");
+        fs::create_dir(&data_dir)?;
+        fs::write(data_dir.join("script.lua"), SCRIPT)?;
+        fs::write(data_dir.join("data.json"), r#"{"new": false}"#)?;
+        let baseline = dir.as_path_untracked().join("baseline.json");
+        let data_dir = data_dir.to_str().wrap_err("non UTF-8 test dir")?;
+        let baseline = baseline.to_str().wrap_err("non UTF-8 test dir")?;
+
+        let run = |extra: &[&str]| -> Result<()> {
+            let mut args = vec!["unittest", "check", "--baseline", baseline];
+            args.extend_from_slice(extra);
+            args.extend(["--", data_dir]);
+            Cli::try_parse_from(args)
+                .wrap_err("failed to parse args")?
+                .run()
+        };
+
+        run(&["--write-baseline"]).wrap_err("writing the baseline should succeed")?;
+        run(&[]).wrap_err("known findings should be suppressed")?;
+
+        fs::write(
+            std::path::Path::new(data_dir).join("data.json"),
+            r#"{"new": true}"#,
+        )?;
+        assert!(run(&[]).is_err(), "expected new finding to fail the run");
+        assert!(logs_contain("[Error] new problem"));
+
+        fs::write(
+            std::path::Path::new(data_dir).join("script.lua"),
+            "function Check() return {} end",
+        )?;
+        run(&[]).wrap_err("no findings should succeed")?;
+        assert!(logs_contain("stale baseline entry"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_lua_path() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local common = require("common")
+            function Check(obj)
+                return common.describe(obj)
+            end
+        "#;
+        const LIBRARY: &str = r#"
+            return {
+                describe = function(obj) return "shared library saw " .. obj.foo end,
+            }
+        "#;
+        let dir = test_temp_dir!();
+        let data_dir = dir.as_path_untracked().join("data");
+        let lib_dir = dir.as_path_untracked().join("lib");
+        fs::create_dir(&data_dir)?;
+        fs::create_dir(&lib_dir)?;
+        fs::write(data_dir.join("script.lua"), SCRIPT)?;
+        fs::write(lib_dir.join("common.lua"), LIBRARY)?;
+        fs::write(data_dir.join("data.json"), r#"{"foo": "bar"}"#)?;
+        let missing_dir = dir.as_path_untracked().join("missing");
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--lua-path",
+            lib_dir.to_str().wrap_err("non UTF-8 test dir")?,
+            "--lua-path",
+            missing_dir.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            data_dir.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("shared library saw bar"));
+        assert!(logs_contain("Lua path directory does not exist"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_sandboxes_os_and_io() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                if os == nil and io == nil then
+                    return "os and io are unavailable"
+                end
+                return "os and io are available"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("os and io are unavailable"));
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--allow-unsafe", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("os and io are available"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_custom_data_extension() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return "conf file has foo=" .. obj.foo
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("app.conf"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--data-ext", "conf=json", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("conf file has foo=bar"));
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--data-ext",
+            "conf=json",
+            "--data-ext",
+            "conf=yaml",
+            "--",
+            dir,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .expect_err("expected conflicting mappings to fail");
+        assert!(
+            format!("{err:#}").contains("already parsed as JSON"),
+            "unexpected error: {err:#}"
+        );
+
+        assert!(
+            Cli::try_parse_from(["unittest", "check", "--data-ext", "conf=ini"]).is_err(),
+            "expected unknown format to be rejected"
+        );
+        let err = Cli::try_parse_from(["unittest", "check", "--data-ext", ".JSON=yaml"])
+            .expect_err("expected a remapped built-in extension to be rejected when parsing");
+        assert!(
+            err.to_string().contains("already parsed as JSON"),
+            "unexpected error: {err}"
+        );
+        Cli::try_parse_from(["unittest", "check", "--data-ext", "yml=yaml"])
+            .wrap_err("mapping a built-in extension to its own format should be allowed")?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_continue_on_parse_error() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return "checked " .. obj.name
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("broken.json"), "{")?;
+        fs::write(
+            dir.as_path_untracked().join("valid.json"),
+            r#"{"name": "valid"}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(!logs_contain("checked valid"));
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--continue-on-parse-error",
+            "--fail-on",
+            "none",
+            "--",
+            dir,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("checked valid"));
+        assert!(logs_contain("[Error] [parse] failed to parse JSON"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_select() -> Result<()> {
+        let dir = test_temp_dir!();
+        fs::write(
+            dir.as_path_untracked().join("security.lua"),
+            r#"function Check() return "security check ran" end"#,
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("style.lua"),
+            r#"function Check() return "style check ran" end"#,
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--select",
+            "*security*.lua",
+            "--select",
+            "*secruity*.lua",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("security check ran"));
+        assert!(!logs_contain("style check ran"));
+        assert!(logs_contain("*secruity*.lua"));
+        assert!(logs_contain("--select glob did not match any check file"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_codes() -> Result<()> {
+        let dir = test_temp_dir!();
+        fs::write(
+            dir.as_path_untracked().join("script.lua"),
+            r#"function Check(obj) if obj.bad then return "bad" end end"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+        let data = std::path::Path::new(dir).join("data.json");
+        let run = || -> Result<()> {
+            Cli::try_parse_from(["unittest", "check", "--", dir])
+                .wrap_err("failed to parse args")?
+                .run()
+        };
+
+        fs::write(&data, r#"{"bad": false}"#)?;
+        assert_eq!(super::exit_code(&run()), ExitCode::SUCCESS);
+        fs::write(&data, r#"{"bad": true}"#)?;
+        assert_eq!(super::exit_code(&run()), ExitCode::from(1));
+        fs::write(&data, "{")?;
+        assert_eq!(super::exit_code(&run()), ExitCode::from(2));
+        assert_eq!(
+            super::exit_code(&Err(super::CancelledError.into())),
+            ExitCode::from(130)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancelled() -> Result<()> {
+        let dir = test_temp_dir!();
+        fs::write(
+            dir.as_path_untracked().join("script.lua"),
+            r#"function Check(obj) return "bad" end"#,
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("script_test.lua"),
+            "function TestNothing() end",
+        )?;
+        fs::write(dir.as_path_untracked().join("data.json"), "{}")?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+        let token = super::CancellationToken::default();
+        assert!(!token.cancel(), "token should not be cancelled yet");
+        assert!(token.is_cancelled());
+
+        for command in ["check", "test"] {
+            let result = Cli::try_parse_from(["unittest", command, "--", dir])
+                .wrap_err("failed to parse args")?
+                .cancellation(token.clone())
+                .run();
+            let err = result.as_ref().expect_err("expected cancellation");
+            assert!(err.is::<super::CancelledError>(), "{command}: {err:#}");
+            assert_eq!(super::exit_code(&result), ExitCode::from(130));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_flush_on_interrupt() -> Result<()> {
+        let dir = test_temp_dir!();
+        fs::write(
+            dir.as_path_untracked().join("script.lua"),
+            r#"function Check(obj) return "bad" end"#,
+        )?;
+        fs::write(dir.as_path_untracked().join("data.json"), "{}")?;
+        let summary = dir.as_path_untracked().join("summary.json");
+        let summary_path = summary.to_str().wrap_err("non UTF-8 test dir")?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+        let token = super::CancellationToken::default();
+        token.cancel();
+
+        let run = |flush: &[&str]| {
+            Cli::try_parse_from(
+                ["unittest", "check", "--summary-json", summary_path]
+                    .into_iter()
+                    .chain(flush.iter().copied())
+                    .chain(["--", dir]),
+            )
+            .wrap_err("failed to parse args")
+            .and_then(|cli| cli.cancellation(token.clone()).run())
+        };
+
+        let err = run(&[]).expect_err("expected cancellation");
+        assert!(err.is::<super::CancelledError>(), "{err:#}");
+        assert!(
+            !summary.exists(),
+            "nothing should be flushed without the flag"
+        );
+
+        let err = run(&["--flush-on-interrupt"]).expect_err("expected cancellation");
+        assert!(err.is::<super::CancelledError>(), "{err:#}");
+        let summary: serde_json::Value = serde_json::from_str(&fs::read_to_string(&summary)?)?;
+        assert_eq!(summary["errors"], 0);
+        assert_eq!(summary["files_checked"], 0);
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_log() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+            function Check(obj)
+                checkonaut.Log("plain message from check")
+                checkonaut.Log("inspecting document", obj)
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("logging should not cause findings")?;
+        assert!(logs_contain("plain message from check"));
+        assert!(logs_contain(r#"inspecting document check="#));
+        assert!(logs_contain(r#"value={"foo":"bar"}"#));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_input_list() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return "checked " .. obj.name
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let dir = dir.as_path_untracked();
+        let script = dir.join("script.lua");
+        let listed = dir.join("listed.json");
+        fs::write(&script, SCRIPT)?;
+        fs::write(&listed, r#"{"name": "listed"}"#)?;
+        fs::write(dir.join("unlisted.json"), r#"{"name": "unlisted"}"#)?;
+        let list = dir.join("changed.txt");
+        fs::write(&list, format!("\n{}\n\n", listed.display()))?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--input-list",
+            list.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            script.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("checked listed"));
+        assert!(!logs_contain("checked unlisted"));
+
+        let missing = dir.join("missing.txt");
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--input-list",
+            missing.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected missing input list to fail");
+        assert!(
+            format!("{err:#}").contains("failed to read input list"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_cache() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return "found " .. obj.foo
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let data_dir = dir.as_path_untracked().join("data");
+        fs::create_dir(&data_dir)?;
+        fs::write(data_dir.join("script.lua"), SCRIPT)?;
+        fs::write(data_dir.join("data.json"), r#"{"foo": "bar"}"#)?;
+        let cache_dir = dir.as_path_untracked().join("cache");
+        let data_dir = data_dir.to_str().wrap_err("non UTF-8 test dir")?;
+        let cache_dir = cache_dir.to_str().wrap_err("non UTF-8 test dir")?;
+        let run = |extra: &[&str]| -> Result<()> {
+            let mut args = vec!["unittest", "check", "--cache-dir", cache_dir];
+            args.extend_from_slice(extra);
+            args.extend(["--", data_dir]);
+            Cli::try_parse_from(args)
+                .wrap_err("failed to parse args")?
+                .run()
+        };
+
+        assert!(
+            run(&["--no-cache"]).is_err(),
+            "expected error but got success"
+        );
+        assert!(!logs_contain("using cached findings"));
+        assert!(run(&[]).is_err(), "expected error but got success");
+        assert!(!logs_contain("using cached findings"));
+        assert!(
+            run(&[]).is_err(),
+            "expected cached findings to fail the run"
+        );
+        assert!(logs_contain("using cached findings"));
+        assert!(logs_contain("found bar"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_cache_options() -> Result<()> {
+        let dir = test_temp_dir!();
+        let data_dir = dir.as_path_untracked().join("data");
+        fs::create_dir(&data_dir)?;
+        fs::write(data_dir.join("script.lua"), "function Check() end")?;
+        fs::write(data_dir.join("data.json"), "{}")?;
+        let cache_dir = dir.as_path_untracked().join("cache");
+        let entries = || -> Result<usize> { Ok(fs::read_dir(&cache_dir)?.count()) };
+        let data_dir = data_dir.to_str().wrap_err("non UTF-8 test dir")?;
+        let run = |extra: &[&str]| -> Result<()> {
+            let cache_dir = cache_dir.to_str().wrap_err("non UTF-8 test dir")?;
+            let mut args = vec!["unittest", "check", "--cache-dir", cache_dir];
+            args.extend_from_slice(extra);
+            args.extend(["--", data_dir]);
+            Cli::try_parse_from(args)
+                .wrap_err("failed to parse args")?
+                .run()
+        };
+
+        // Every option that may change the findings gets entries of its own.
+        let mut expected = 0;
+        for extra in [
+            &["--now", "1700000000"][..],
+            &["--now", "1700000001"],
+            &["--now", "1700000000", "--seed", "1"],
+            &["--now", "1700000000", "--context-json", "{}"],
+            &["--now", "1700000000", "--normalize-numbers"],
+            &["--now", "1700000000", "--data-ext", "txt=json"],
+            &["--now", "1700000000", "--format-override", "*.json=yaml"],
+            &[],
+        ] {
+            run(extra)?;
+            expected += 1;
+            assert_eq!(entries()?, expected, "{extra:?} should not reuse entries");
+            run(extra)?;
+            assert_eq!(entries()?, expected, "{extra:?} should reuse its entries");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_finding_constructors() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+            function Check()
+                return {
+                    checkonaut.Error("constructed error", { id = "some-rule", path = "/foo" }),
+                    checkonaut.Warn("constructed warning"),
+                }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain(
+            "[Error] [some-rule] constructed error (at path /foo)"
+        ));
+        assert!(logs_contain("[Warning] constructed warning"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_context_raw_and_format() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj, context)
+                local issues = { }
+                if context.format ~= "yaml" then
+                    table.insert(issues, "unexpected format " .. context.format)
+                end
+                if context.raw:sub(-1) ~= "\n" then
+                    table.insert(issues, "missing trailing newline")
+                end
+                if context.raw:find("\t") then
+                    table.insert(issues, "file contains tabs")
+                end
+                return issues
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("data.yaml"), "foo:\tbar")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let res = cmd.run();
+        assert!(res.is_err(), "expected error but got success");
+        assert!(logs_contain("missing trailing newline"));
+        assert!(logs_contain("file contains tabs"));
+        assert!(!logs_contain("unexpected format"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_strict() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        fs::write(dir.as_path_untracked().join("empty.csv"), "name,owner\n")?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("unchecked files should pass without --strict")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--strict", "--", dir])
+            .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .expect_err("expected unchecked file to fail the run");
+        assert!(
+            format!("{err:#}").contains("1 data file not checked by any check"),
+            "unexpected error: {err:#}"
+        );
+        assert!(logs_contain("no check was run against data file"));
+        assert!(logs_contain("empty.csv"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_fatal_severity() -> Result<()> {
+        const FATAL: &str = r#"
+            function Check()
+                return { message = "data is unusable", severity = "fatal" }
+            end
+        "#;
+        const OTHER: &str = r#"
+            function Check()
+                return "should have been skipped"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("a_fatal.lua"), FATAL)?;
+        fs::write(dir.as_path_untracked().join("b_other.lua"), OTHER)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--fail-on", "none", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected fatal finding to fail the run regardless of --fail-on"
+        );
+        assert!(logs_contain("fatal findings found by check"));
+        assert!(logs_contain("[Fatal] data is unusable"));
+        assert!(
+            !logs_contain("should have been skipped"),
+            "expected remaining checks to be skipped"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_report() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { "an error", { message = "a warning", severity = "warning" } }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let report = dir.as_path_untracked().join("report.txt");
+        let report_arg = report.to_str().wrap_err("non UTF-8 test dir")?;
+        let path = dir
+            .as_path_untracked()
+            .join("data.json")
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?
+            .to_string();
+        let checks = dir
+            .as_path_untracked()
+            .join("script.lua")
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?
+            .to_string();
+
+        let cmd = Cli::try_parse_from([
+            "unittest", "check", "--report", report_arg, "--checks", &checks, "--", &path,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error to fail the run");
+
+        let report = fs::read_to_string(&report)?;
+        assert!(
+            report.contains(&format!("{path}: {checks}: [Error] an error")),
+            "unexpected report: {report}"
+        );
+        assert!(
+            report.contains(&format!("{path}: {checks}: [Warning] a warning")),
+            "unexpected report: {report}"
+        );
+        assert!(
+            report.contains("checked 1 file with 1 check: 1 error, 1 warning"),
+            "unexpected report: {report}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_search_is_deterministic() -> Result<()> {
+        let dir = test_temp_dir!();
+        for sub in ["a", "b", "c"] {
+            let sub = dir.as_path_untracked().join(sub);
+            fs::create_dir(&sub)?;
+            for i in 0..10 {
+                fs::write(sub.join(format!("data{i}.json")), "{}")?;
+                fs::write(sub.join(format!("check{i}.lua")), "")?;
+                fs::write(sub.join(format!("check{i}_test.lua")), "")?;
+            }
+        }
+        let search = |threads| -> Result<FileSearchResult> {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?;
+            let paths = vec![dir.as_path_untracked().to_path_buf()];
+            pool.install(|| {
+                FileSearcher::default()
+                    .include_check_files(true)
+                    .include_test_files(true)
+                    .include_data_files(true)
+                    .search(paths)
+                    .wrap_err("failed to search")
+            })
+        };
+
+        let single = search(1)?;
+        assert_eq!(single.data_files.len(), 30);
+        assert_eq!(single, search(8)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_prelude() -> Result<()> {
+        const PRELUDE: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function Bad(message)
+                return checkonaut.Warn(message)
+            end
+        "#;
+        const SCRIPT: &str = r#"
+            function Check()
+                return Bad("from the prelude")
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let checks = dir.as_path_untracked().join("checks");
+        fs::create_dir(&checks)?;
+        fs::write(checks.join("script.lua"), SCRIPT)?;
+        fs::write(checks.join("data.json"), r#"{"foo": "bar"}"#)?;
+        let prelude = dir.as_path_untracked().join("prelude.lua");
+        fs::write(&prelude, PRELUDE)?;
+        let broken = dir.as_path_untracked().join("broken.lua");
+        fs::write(&broken, "this is not Lua")?;
+        let checks = checks.to_str().wrap_err("non UTF-8 test dir")?;
+        let prelude = prelude.to_str().wrap_err("non UTF-8 test dir")?;
+        let broken = broken.to_str().wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--prelude",
+            prelude,
+            "--fail-on",
+            "warning",
+            "--",
+            checks,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected the prelude's finding to fail the run"
+        );
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--prelude", prelude, "--", checks])
+            .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("warnings should not fail the run")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--prelude", broken, "--", checks])
+            .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .expect_err("expected a broken prelude to fail the run");
+        assert!(
+            format!("{err:#}").contains(&format!("failed to load prelude '{broken}'")),
+            "unexpected error: {err:#}"
+        );
+
+        assert!(
+            Cli::try_parse_from(["unittest", "check", "--prelude", "missing.lua"]).is_err(),
+            "expected a missing prelude to be rejected"
+        );
+
+        Ok(())
+    }
+
+    /// Serves the responses, in order, to one connection each, on a local port.
+    fn serve_http(responses: Vec<String>) -> Result<String> {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        std::thread::spawn(move || {
+            for response in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        Ok(format!("http://{addr}"))
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_url_input() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "remote file is bad"
+                end
+                return nil
+            end
+        "#;
+        let body = r#"{"bad": true}"#;
+        let base = serve_http(vec![
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            ),
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ])?;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        let checks = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        // The format comes from the content type, as the URL has no extension.
+        let url = format!("{base}/config?version=1");
+        let cmd = Cli::try_parse_from(["unittest", "check", "--checks", checks, "--", &url])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the remote file to fail");
+        assert!(logs_contain("remote file is bad"));
+        assert!(logs_contain(&url));
+
+        let url = format!("{base}/missing.json");
+        let cmd = Cli::try_parse_from(["unittest", "check", "--checks", checks, "--", &url])
+            .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected a missing URL to fail");
+        assert!(
+            format!("{err:#}").contains("unexpected HTTP status: 404 Not Found"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_inline_data() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj, ctx)
+                if obj.bad then
+                    return "inline " .. ctx.format .. " is bad"
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let script = dir.as_path_untracked().join("script.lua");
+        fs::write(&script, SCRIPT)?;
+        let script = script.to_str().wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--only",
+            script,
+            "--data",
+            r#"{"bad": false}"#,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("the inline data should pass")?;
+        assert!(logs_contain("checked 1 file with 1 check"));
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--only",
+            script,
+            "--data-format",
+            "yaml",
+            "--data",
+            "bad: false",
+            "--data",
+            "bad: true",
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the second document to fail");
+        assert!(logs_contain("<inline #1>"));
+        assert!(logs_contain("inline yaml is bad"));
+
+        assert!(
+            Cli::try_parse_from(["unittest", "check", "--data-format", "nope", "--data", "{}"])
+                .is_err(),
+            "expected an unknown format to be rejected"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_parallel_documents() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj, context)
+                if context.format ~= "yaml" then
+                    return "unexpected format " .. context.format
+                end
+                return "document " .. obj.n
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        let data = (0..20)
+            .map(|n| format!("n: {n}\n"))
+            .collect::<Vec<_>>()
+            .join("---\n");
+        fs::write(dir.as_path_untracked().join("data.yaml"), data)?;
+        let report = dir.as_path_untracked().join("report.txt");
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--parallel-documents",
+            "--report",
+            report.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected every document to fail");
+
+        let report = fs::read_to_string(&report)?;
+        assert!(!report.contains("unexpected format"), "{report}");
+        let positions = (0..20)
+            .map(|n| report.find(&format!("document {n} (document #{n})")))
+            .collect::<Option<Vec<_>>>()
+            .wrap_err_with(|| format!("missing findings in report: {report}"))?;
+        assert!(
+            positions.is_sorted(),
+            "findings out of document order: {report}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_context_json() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj, context)
+                if context.input == nil then
+                    return "no context input"
+                end
+                if context.input.env == "prod" and not obj.replicated then
+                    return "must be replicated in " .. context.input.env
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let script = dir.as_path_untracked().join("script.lua");
+        fs::write(&script, SCRIPT)?;
+        let script = script.to_str().wrap_err("non UTF-8 test dir")?;
+        let context = dir.as_path_untracked().join("context.json");
+        fs::write(&context, r#"{"env": "prod"}"#)?;
+        let context = format!("@{}", context.to_str().wrap_err("non UTF-8 test dir")?);
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--only",
+            script,
+            "--context-json",
+            r#"{"env": "dev"}"#,
+            "--data",
+            "{}",
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("dev should pass")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--only",
+            script,
+            "--context-json",
+            &context,
+            "--data",
+            "{}",
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected prod to fail");
+        assert!(logs_contain("must be replicated in prod"));
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--only", script, "--data", "{}"])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected a missing input to fail");
+        assert!(logs_contain("no context input"));
+
+        assert!(
+            Cli::try_parse_from(["unittest", "check", "--context-json", "{nope"]).is_err(),
+            "expected invalid JSON to be rejected"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_toml_multidoc() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "document is bad"
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.toml"),
+            "bad = false\n+++\n\n+++\nbad = true\n",
+        )?;
+        let path = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        // Without splitting, the delimiter is invalid TOML.
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", path])
+            .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected the file to fail to parse");
+        assert!(format!("{err:#}").contains("failed to parse TOML"));
+
+        // The empty document is skipped, so the bad one is the second.
+        let cmd = Cli::try_parse_from(["unittest", "check", "--toml-multidoc", "--", path])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the second document to fail");
+        assert!(logs_contain("document is bad (document #1"));
+
+        fs::write(
+            dir.as_path_untracked().join("data.toml"),
+            "bad = false\n---\nbad =\n",
+        )?;
+        let cmd = Cli::try_parse_from(["unittest", "check", "--toml-multidoc=---", "--", path])
+            .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .expect_err("expected the second document to fail to parse");
+        assert!(
+            format!("{err:#}").contains("failed to parse TOML document 2"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_diff_base() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "file is bad"
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let git = |args: &[&str]| -> Result<()> {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(dir.as_path_untracked())
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()?
+                .status;
+            eyre::ensure!(status.success(), "git {args:?} failed");
+            Ok(())
+        };
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("unchanged.json"),
+            r#"{"bad": true}"#,
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("changed.json"),
+            r#"{"bad": false}"#,
+        )?;
+        git(&["init", "--quiet"])?;
+        git(&["add", "."])?;
+        git(&["commit", "--quiet", "-m", "initial"])?;
+        fs::write(
+            dir.as_path_untracked().join("changed.json"),
+            r#"{"bad": true, "changed": true}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--diff-base", "HEAD", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the changed file to fail");
+        assert!(logs_contain("changed.json"));
+        assert!(!logs_contain("unchanged.json"));
+
+        git(&["commit", "--quiet", "-am", "change"])?;
+        let cmd = Cli::try_parse_from(["unittest", "check", "--diff-base", "HEAD", "--", dir])
+            .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("nothing should be checked without changes")?;
+        assert!(logs_contain("no changed data files to check"));
+
+        // Untracked files are new, so they're checked, unless git ignores them.
+        fs::write(Path::new(dir).join(".gitignore"), "ignored.json\n")?;
+        fs::write(Path::new(dir).join("ignored.json"), r#"{"bad": true}"#)?;
+        fs::write(Path::new(dir).join("untracked.json"), r#"{"bad": true}"#)?;
+        let cmd = Cli::try_parse_from(["unittest", "check", "--diff-base", "HEAD", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the untracked file to fail");
+        assert!(logs_contain("untracked.json"));
+        assert!(!logs_contain("ignored.json"));
+
+        let cmd =
+            Cli::try_parse_from(["unittest", "check", "--diff-base", "no-such-ref", "--", dir])
+                .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected an invalid ref to fail");
+        assert!(
+            format!("{err:#}").contains("failed to diff against git ref: no-such-ref"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_test_only_changed_checks() -> Result<()> {
+        const CHECK: &str = r#"
+            function Check()
+                return nil
+            end
+        "#;
+        const BROKEN_TEST: &str = r#"
+            function TestBroken()
+                error("unchanged tests should not run")
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let git = |args: &[&str]| -> Result<()> {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(dir.as_path_untracked())
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()?
+                .status;
+            eyre::ensure!(status.success(), "git {args:?} failed");
+            Ok(())
+        };
+        fs::write(dir.as_path_untracked().join("broken.lua"), CHECK)?;
+        fs::write(dir.as_path_untracked().join("broken_test.lua"), BROKEN_TEST)?;
+        fs::write(dir.as_path_untracked().join("working.lua"), CHECK)?;
+        fs::write(
+            dir.as_path_untracked().join("working_test.lua"),
+            "function TestWorking() end",
+        )?;
+        git(&["init", "--quiet"])?;
+        git(&["add", "."])?;
+        git(&["commit", "--quiet", "-m", "initial"])?;
+        let path = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        // Only the check changed, so only its test runs.
+        fs::write(
+            dir.as_path_untracked().join("working.lua"),
+            format!("{CHECK}\n-- changed"),
+        )?;
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--only-changed-checks",
+            "HEAD",
+            "--",
+            path,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("only the changed check's test should run")?;
+        assert!(logs_contain("ran 1 test in 1 file"));
+
+        let cmd = Cli::try_parse_from(["unittest", "test", "--", path])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the broken test to fail");
+
+        let plain = test_temp_dir!();
+        fs::write(plain.as_path_untracked().join("working.lua"), CHECK)?;
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "test",
+            "--only-changed-checks",
+            "HEAD",
+            "--",
+            plain
+                .as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected a non-git directory to fail");
+        assert!(
+            format!("{err:#}").contains("not in a git repository"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_summary_event() -> Result<()> {
+        use std::sync::{Arc, Mutex, PoisonError};
+        use tracing_subscriber::fmt::{MakeWriter, format::FmtSpan};
+
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let mut captured = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+                captured.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        impl MakeWriter<'_> for Captured {
+            type Writer = Self;
+            fn make_writer(&self) -> Self {
+                self.clone()
+            }
+        }
+
+        const SCRIPT: &str = r#"
+            function Check()
+                return { message = "a warning", severity = "warning" }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        // The event and the span's totals are logged even with `--quiet`.
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--quiet",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(captured.clone())
+            .with_ansi(false)
+            .with_span_events(FmtSpan::CLOSE)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || cmd.run())
+            .wrap_err("warnings should not fail the run")?;
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone())?;
+        assert!(
+            logs.contains("files_checked=1 checks=1 errors=0 warnings=1 duration_ms="),
+            "{logs}"
+        );
+        let close = (logs.lines())
+            .find(|line| line.contains(": close "))
+            .wrap_err("the check_run span was not closed")?;
+        assert!(
+            close.contains("check_run{files_checked=1 errors=0 warnings=1 duration_ms="),
+            "{close}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_format_github() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return "an error"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--format",
+            "github",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let result = cmd.run();
+        assert_eq!(super::exit_code(&result), ExitCode::from(1));
+
+        let finding = CheckError {
+            severity: CheckSeverity::Warning,
+            error: "bad: 100%\nreally".to_string(),
+            location: Some(Location {
+                line: Some(3),
+                column: Some(5),
+                path: Some("/spec".to_string()),
+            }),
+            id: Some("rule".to_string()),
+            document_index: None,
+            function: None,
+            fix: None,
+        };
+        assert_eq!(
+            github_annotation(Path::new("a,b.json"), Some(Path::new("c:d.lua")), &finding),
+            "::warning file=a%2Cb.json,line=3,col=5,title=c%3Ad.lua::[rule] bad: 100%25%0Areally (at /spec)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_plain() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return "bad"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--plain", "--", dir])
+            .wrap_err("failed to parse args")?;
+        let result = cmd.run();
+        assert_eq!(super::exit_code(&result), ExitCode::from(1));
+
+        assert!(
+            Cli::try_parse_from(["unittest", "check", "--plain", "--format", "github", dir])
+                .is_err(),
+            "--plain should conflict with --format"
+        );
+
+        assert_eq!(
+            plain_line(
+                CheckSeverity::Warning,
+                Path::new("a\tb.json"),
+                Some(Path::new("c.lua")),
+                "bad:\n\\really"
+            ),
+            "warning\ta\\tb.json\tc.lua\tbad:\\n\\\\really"
+        );
+        assert_eq!(
+            plain_line(CheckSeverity::Error, Path::new("d.json"), None, "parse"),
+            "error\td.json\t-\tparse"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_output() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return "bad"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+        let output = dir.as_path_untracked().join("out/nested/findings.txt");
+        let data = dir.as_path_untracked().join("data.json");
+        let data = data.to_str().wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--format",
+            "github",
+            "--output",
+            output.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+
+        let output = fs::read_to_string(&output)?;
+        assert!(
+            output.starts_with(&format!("::error file={data}")) && output.contains("::bad"),
+            "unexpected output: {output}"
+        );
+
+        let blocker = dir.as_path_untracked().join("blocker");
+        fs::write(&blocker, "")?;
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--plain",
+            "--output",
+            blocker
+                .join("findings.txt")
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected the output to fail to write");
+        assert!(
+            format!("{err:#}").contains("failed to create output directory"),
+            "{err:#}"
+        );
+
+        let human = dir.as_path_untracked().join("human.txt");
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--output",
+            human.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .expect_err("expected --output without a format to fail");
+        assert!(format!("{err:#}").contains("--report"), "{err:#}");
+        assert!(!human.exists(), "no output file should be created");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_summary_json() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return { "an error", { message = "a warning", severity = "warning" } }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("a.json"), "{}")?;
+        fs::write(dir.as_path_untracked().join("b.json"), "{}")?;
+        let summary = dir.as_path_untracked().join("summary.json");
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--summary-json",
+            summary.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+
+        let summary: serde_json::Value = serde_json::from_str(&fs::read_to_string(&summary)?)?;
+        assert_eq!(summary["errors"], 2);
+        assert_eq!(summary["warnings"], 2);
+        assert_eq!(summary["files_checked"], 2);
+        assert_eq!(summary["checks_run"], 1);
+        assert!(summary["duration_ms"].is_u64(), "{summary}");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_fail_fast() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "an error"
+                end
+                return { message = "a warning", severity = "warning" }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        for i in 0..100 {
+            fs::write(
+                dir.as_path_untracked().join(format!("data{i}.json")),
+                r#"{"bad": false}"#,
+            )?;
+        }
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--fail-fast", "--", dir])
+            .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("warnings should not fail the run")?;
+        assert!(
+            !logs_contain("stopped checking at the first failing data file"),
+            "warnings should not stop the run"
+        );
+
+        fs::write(Path::new(dir).join("data50.json"), r#"{"bad": true}"#)?;
+        let cmd = Cli::try_parse_from(["unittest", "check", "--fail-fast", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain(
+            "stopped checking at the first failing data file"
+        ));
+        assert!(logs_contain("[Error] an error"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_gzip() -> Result<()> {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "compressed document is bad"
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"bad: true\n")?;
+        fs::write(
+            dir.as_path_untracked().join("data.yaml.gz"),
+            encoder.finish()?,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("compressed document is bad"));
+
+        fs::write(Path::new(dir).join("data.yaml.gz"), "not gzip")?;
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        let err = cmd.run().expect_err("expected corrupt gzip to fail");
+        assert!(
+            format!("{err:#}").contains("failed to decompress gzip data file"),
+            "unexpected error: {err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_file() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return { message = "just a warning", severity = "warning" }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let checks = dir.as_path_untracked().join("checks");
+        let data = dir.as_path_untracked().join("data");
+        fs::create_dir(&checks)?;
+        fs::create_dir(&data)?;
+        fs::write(checks.join("script.lua"), SCRIPT)?;
+        fs::write(data.join("data.json"), r#"{"foo": "bar"}"#)?;
+        let config = dir.as_path_untracked().join("checkonaut.toml");
+        fs::write(&config, "fail_on = \"warning\"\nchecks = [\"checks\"]\n")?;
+        let config = config.to_str().wrap_err("non UTF-8 test dir")?;
+        let data = data.to_str().wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--config", config, "--", data])
+            .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected the configured threshold to fail the run"
+        );
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--config",
+            config,
+            "--fail-on",
+            "error",
+            "--",
+            data,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("flags should take precedence over the configuration")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "--config", "missing.toml", "check"])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected a missing config to fail");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_file_overrides() -> Result<()> {
+        let dir = test_temp_dir!();
+        let data = dir.as_path_untracked().join("data");
+        fs::create_dir(&data)?;
+        fs::write(
+            data.join("script.lua"),
+            r#"function Check() return "checked" end"#,
+        )?;
+        for name in ["data.json", ".hidden.json", "skip.json"] {
+            fs::write(data.join(name), "{}")?;
+        }
+        let config = dir.as_path_untracked().join("checkonaut.toml");
+        fs::write(
+            &config,
+            "dotfiles = true\nexclude = [\"skip.json\"]\ntimeout = 5\n",
+        )?;
+        let report = dir.as_path_untracked().join("report.txt");
+        let report_path = report.to_str().wrap_err("non UTF-8 test dir")?;
+        let config = config.to_str().wrap_err("non UTF-8 test dir")?;
+        let data = data.to_str().wrap_err("non UTF-8 test dir")?;
+
+        let checked = |flags: &[&str]| -> Result<Vec<&str>> {
+            let mut args = vec![
+                "unittest",
+                "--config",
+                config,
+                "check",
+                "--report",
+                report_path,
+            ];
+            args.extend(flags);
+            args.extend(["--", data]);
+            let cmd = Cli::try_parse_from(args).wrap_err("failed to parse args")?;
+            assert!(cmd.run().is_err(), "expected findings with {flags:?}");
+            let report = fs::read_to_string(&report)?;
+            Ok([".hidden.json", "data.json", "skip.json"]
+                .into_iter()
+                .filter(|file| report.contains(&format!("/{file}: ")))
+                .collect())
+        };
+
+        assert_eq!(checked(&[])?, [".hidden.json", "data.json"]);
+        assert_eq!(checked(&["--dotfiles=false"])?, ["data.json"]);
+        assert_eq!(
+            checked(&["--exclude", "data.json"])?,
+            [".hidden.json", "skip.json"]
+        );
+
+        fs::write(config, "exclude = [\"[\"]\n")?;
+        let cmd = Cli::try_parse_from(["unittest", "--config", config, "check", "--", data])
+            .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .err()
+            .wrap_err("expected an invalid exclude to fail")?;
+        assert!(
+            format!("{err:#}").contains("invalid exclude in configuration"),
+            "{err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_max_findings() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return "an error"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        for i in 0..10 {
+            fs::write(
+                dir.as_path_untracked().join(format!("data{i}.json")),
+                r#"{"foo": "bar"}"#,
+            )?;
+        }
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--max-findings",
+            "3",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("... and 7 more findings suppressed"));
+        assert!(logs_contain("data2.json"));
+        assert!(!logs_contain("data3.json"));
+        assert!(logs_contain("10 errors"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_multiple_functions() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return "from the plain check"
+            end
+
+            function Check_Naming()
+                return "bad name"
+            end
+
+            function CheckHelper()
+                return "helpers are not checks"
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("[Error] from the plain check"));
+        assert!(!logs_contain("from the plain check (from Check)"));
+        assert!(logs_contain("[Error] bad name (from Check_Naming)"));
+        assert!(!logs_contain("helpers are not checks"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verbose_log_filter() -> Result<()> {
+        let filter = |args: &[&str]| -> Result<String> {
+            let cli = Cli::try_parse_from(["unittest"].iter().chain(args).chain(&["test"]))
+                .wrap_err("failed to parse args")?;
+            Ok(cli.log_filter().to_string())
+        };
+
+        // The environment may set RUST_LOG, so always pass --logger explicitly when it matters.
+        assert_eq!(filter(&["--logger", "error", "-vv"])?, "error");
+        if std::env::var_os("RUST_LOG").is_none() {
+            assert_eq!(filter(&[])?, "warn,checkonaut=info");
+            assert_eq!(filter(&["-v"])?, "warn,checkonaut=debug");
+            assert_eq!(filter(&["-vv"])?, "debug,checkonaut=trace");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch_relevant_changes() -> Result<()> {
+        use notify::{
+            Event, EventKind,
+            event::{AccessKind, CreateKind, ModifyKind, RemoveKind},
+        };
+
+        let modify = EventKind::Modify(ModifyKind::Any);
+        let relevant = |kind: EventKind, path: &str, extensions: &DataExtensions| {
+            is_relevant_change(&Event::new(kind).add_path(path.into()), extensions)
+        };
+        let known = DataExtensions::default();
+
+        assert!(relevant(modify, "checks/script.lua", &known));
+        assert!(relevant(modify, "data.yaml", &known));
+        assert!(relevant(
+            EventKind::Create(CreateKind::File),
+            "new.json",
+            &known
+        ));
+        assert!(relevant(
+            EventKind::Remove(RemoveKind::File),
+            "old.toml",
+            &known
+        ));
+        // Reading a file doesn't change it, and other files can't change the findings.
+        assert!(!relevant(
+            EventKind::Access(AccessKind::Any),
+            "data.yaml",
+            &known
+        ));
+        assert!(!relevant(modify, "notes.txt", &known));
+        assert!(!relevant(modify, "app.conf", &known));
+
+        let custom =
+            DataExtensions::new(&["conf=json".parse().map_err(|e| eyre::eyre!("{e}"))?])?;
+        assert!(relevant(modify, "app.conf", &custom));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_color() -> Result<()> {
+        let ansi = |args: &[&str]| -> Result<Option<bool>> {
+            let cli = Cli::try_parse_from(["unittest"].iter().chain(args).chain(&["test"]))
+                .wrap_err("failed to parse args")?;
+            Ok(cli.ansi())
+        };
+
+        assert_eq!(ansi(&[])?, None);
+        assert_eq!(ansi(&["--color", "auto"])?, None);
+        assert_eq!(ansi(&["--color", "always"])?, Some(true));
+        assert_eq!(ansi(&["--color", "never"])?, Some(false));
+        assert!(
+            Cli::try_parse_from(["unittest", "--color", "sometimes", "test"]).is_err(),
+            "expected an unknown colour choice to be rejected"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_suggested_fix() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function Check()
+                return {
+                    { message = "image uses latest", fix = "pin the image to a digest" },
+                    checkonaut.Warn("replicas too low", { fix = { path = "/replicas", value = 3 } }),
+                }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain(
+            "image uses latest (suggested fix: pin the image to a digest)"
+        ));
+        assert!(logs_contain(
+            r#"suggested fix: {\"path\":\"/replicas\",\"value\":3}"#
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_fix() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                local findings = {}
+                if obj.image == "nginx:latest" then
+                    table.insert(findings, {
+                        message = "image uses the latest tag",
+                        line = 2,
+                        fix = { replace = "latest", with = "1.27" },
+                    })
+                end
+                if obj.replicas < 2 then
+                    table.insert(findings, { message = "too few replicas", fix = "add replicas" })
+                end
+                return findings
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        let data = dir.as_path_untracked().join("data.yaml");
+        fs::write(
+            &data,
+            "# latest is not checked here\nimage: nginx:latest\nreplicas: 1\n",
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--fix", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected the finding without an applicable fix to remain"
+        );
+        assert_eq!(
+            fs::read_to_string(&data)?,
+            "# latest is not checked here\nimage: nginx:1.27\nreplicas: 1\n"
+        );
+        assert_eq!(
+            fs::read_to_string(Path::new(dir).join("data.yaml.orig"))?,
+            "# latest is not checked here\nimage: nginx:latest\nreplicas: 1\n"
+        );
+        assert!(logs_contain("applied fixes; re-running checks to verify"));
+        // Only the verifying run reports findings, so the fixed one never is.
+        assert!(!logs_contain("image uses the latest tag"));
+        assert!(logs_contain("too few replicas"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_fix_existing_backup() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                return { message = "bad value", line = 1, fix = { replace = "bad", with = "good" } }
+            end
+        "#;
+        let dir = test_temp_dir!();
+        let root = dir.as_path_untracked();
+        fs::write(root.join("script.lua"), SCRIPT)?;
+        fs::write(root.join("a.yaml"), "value: bad\n")?;
+        fs::write(root.join("b.yaml"), "value: bad\n")?;
+        fs::write(root.join("b.yaml.orig"), "an earlier backup\n")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--fix",
+            "--",
+            root.to_str().wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .err()
+            .wrap_err("expected the backup to be refused")?;
+        assert!(
+            format!("{err:#}").contains("backup file already exists"),
+            "{err:#}"
+        );
+        // No file is fixed, not even those before the one with a backup.
+        assert_eq!(fs::read_to_string(root.join("a.yaml"))?, "value: bad\n");
+        assert!(!root.join("a.yaml.orig").exists());
+        assert_eq!(fs::read_to_string(root.join("b.yaml"))?, "value: bad\n");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_env() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(env, ctx)
+                assert(ctx.format == "env")
+                local expected = {
+                    PLAIN = "value",
+                    EXPORTED = "bar",
+                    DOUBLE = "line\n\"quoted\"",
+                    SINGLE = "raw \\n",
+                    COMMENTED = "kept",
+                    EMPTY = "",
+                }
+                for key, value in pairs(expected) do
+                    if env[key] ~= value then
+                        return key .. " was " .. tostring(env[key])
+                    end
+                end
+                return "env parsed"
+            end
+        "#;
+        const ENV: &str = r#"
+# A comment
+PLAIN=value
+
+export EXPORTED="bar"
+DOUBLE="line\n\"quoted\""
+SINGLE='raw \n'
+COMMENTED=kept # trailing comment
+EMPTY=
+"#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join(".env"), ENV)?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected no data files to be found without --dotfiles"
+        );
+        assert!(!logs_contain("env parsed"));
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--dotfiles", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected error but got success");
+        assert!(logs_contain("env parsed"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_io() -> Result<()> {
+        use std::io;
+
+        let mut attempts = 0;
+        let flaky = |attempts: &mut u32| {
+            *attempts += 1;
+            if *attempts <= 2 {
+                Err(io::Error::other("flaky"))
+            } else {
+                Ok(*attempts)
+            }
+        };
+
+        assert!(retry_io(1, || flaky(&mut attempts)).is_err());
+        assert_eq!(attempts, 2);
+
+        attempts = 0;
+        assert_eq!(retry_io(2, || flaky(&mut attempts))?, 3);
+
+        attempts = 0;
+        let result = retry_io(5, || -> io::Result<()> {
+            attempts += 1;
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1, "missing files must not be retried");
+
+        // The wait between attempts doubles every time, starting at 50 ms.
+        let mut tried_at = Vec::new();
+        let result = retry_io(2, || -> io::Result<()> {
+            tried_at.push(std::time::Instant::now());
+            Err(io::ErrorKind::Interrupted.into())
+        });
+        assert!(result.is_err());
+        let waits = (tried_at.windows(2))
+            .map(|w| w[1] - w[0])
+            .collect::<Vec<_>>();
+        assert_eq!(waits.len(), 2, "expected two retries");
+        assert!(waits[0] >= Duration::from_millis(50), "{waits:?}");
+        assert!(waits[1] >= Duration::from_millis(100), "{waits:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_read_retries() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check()
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"foo": "bar"}"#,
+        )?;
+
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+        let run = |input: &str| {
+            Cli::try_parse_from(["unittest", "check", "--read-retries", "3", "--", input])
+                .wrap_err("failed to parse args")?
+                .run()
+        };
+
+        run(dir).wrap_err("failed to run checks with read retries")?;
+
+        // A missing data file won't appear by trying again, so it isn't retried.
+        let missing = Path::new(dir).join("missing.json");
+        let err = run(missing.to_str().wrap_err("non UTF-8 test dir")?)
+            .expect_err("expected a missing data file to fail");
+        assert!(format!("{err:#}").contains("missing.json"), "{err:#}");
+        assert!(!logs_contain("retrying IO operation"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_markdown_front_matter() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(doc, ctx)
+                assert(ctx.format == "markdown")
+                if doc.title == nil then
+                    return "missing title"
+                end
+                if doc.__body ~= nil then
+                    return "body: " .. doc.__body
+                end
+                return nil
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("post.md"),
+            "---\ntitle: Hello\n---\n# Hello world\n",
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("notes.markdown"),
+            "Just some notes.\n",
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("files without front matter should be skipped")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--markdown-body", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the body to be reported");
+        assert!(logs_contain("body: # Hello world"));
+        assert!(!logs_contain("missing title"));
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--markdown-without-front-matter",
+            "--",
+            dir,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the notes to have no title");
+        assert!(logs_contain("missing title"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_markdown_without_front_matter() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(doc)
+                return "checked " .. doc.title
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("README.md"), "# Readme\n")?;
+        fs::write(
+            dir.as_path_untracked().join("break.md"),
+            "---\n\nText after a thematic break.\n",
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"title": "x"}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        // Only the JSON file is checked; the Markdown files have nothing to check, even with
+        // `--strict`, and a leading `---` line without a closing one isn't front matter.
+        let cmd = Cli::try_parse_from(["unittest", "check", "--strict", "--", dir])
+            .wrap_err("failed to parse args")?;
+        let err = cmd
+            .run()
+            .err()
+            .wrap_err("expected the JSON file to be reported")?;
+        assert!(err.is::<super::FindingsError>(), "{err:#}");
+        assert!(logs_contain("checked x"));
+        assert!(!logs_contain("no check was run against data file"));
+        assert!(!logs_contain("not terminated"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_numbers_across_formats() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+
+            function Check(doc, ctx)
+                local issues = { }
+                local function expect(ok, what)
+                    if not ok then
+                        table.insert(issues, ctx.format .. ": " .. what)
+                    end
+                end
+                expect(math.type(doc.big) == "integer", "big is not an integer")
+                expect(doc.big == 9007199254740993, "big lost precision")
+                expect(math.type(doc.frac) == "float", "frac is not a float")
+                expect(not checkonaut.IsInteger(doc.frac), "frac is an integer")
+                expect(checkonaut.IsInteger(doc.whole), "whole is not an integer")
+                expect(not checkonaut.IsInteger(1e300), "1e300 is an integer")
+                expect(type(doc.str) == "string", "str is not a string")
+                expect(not checkonaut.IsInteger(doc.str), "str is an integer")
+                expect(checkonaut.AsNumber(doc.str) == 42, "str is not 42")
+                expect(math.type(checkonaut.AsNumber(doc.str)) == "integer", "str is not integral")
+                expect(checkonaut.AsNumber(" 1.5 ") == 1.5, "1.5 was not parsed")
+                expect(checkonaut.AsNumber("nope") == nil, "nope was parsed")
+                expect(checkonaut.AsNumber("4\xff") == nil, "non UTF-8 was parsed")
+                expect(checkonaut.AsNumber(doc.frac) == doc.frac, "frac changed")
+                table.insert(issues, ctx.format .. ": whole is " .. math.type(doc.whole))
+                return issues
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"big": 9007199254740993, "frac": 1.5, "whole": 2.0, "str": "42"}"#,
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("data.yaml"),
+            "big: 9007199254740993\nfrac: 1.5\nwhole: 2.0\nstr: \"42\"\n",
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("data.toml"),
+            "big = 9007199254740993\nfrac = 1.5\nwhole = 2.0\nstr = \"42\"\n",
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the types to be reported");
+        for format in ["json", "yaml", "toml"] {
+            assert!(logs_contain(&format!("{format}: whole is float")));
+        }
+
+        let cmd = Cli::try_parse_from(["unittest", "check", "--normalize-numbers", "--", dir])
+            .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the types to be reported");
+        for format in ["json", "yaml", "toml"] {
+            assert!(logs_contain(&format!("{format}: whole is integer")));
+        }
+        assert!(!logs_contain("big is not"));
+        assert!(!logs_contain("big lost"));
+        assert!(!logs_contain("frac is"));
+        assert!(!logs_contain("str is"));
+        assert!(!logs_contain("was parsed"));
+        assert!(!logs_contain("was not parsed"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_stdin_source() -> Result<()> {
+        const SCRIPT: &str = r#"
+            local checkonaut = require("@checkonaut")
+            Limit = checkonaut.ReadJSON("limits.json").max
+
+            function Check()
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("limits.json"), r#"{"max": 3}"#)?;
+
+        // A check from stdin resolves files as if it were in the current directory.
+        let mut source =
+            SourceCode::new(dir.as_path_untracked().join("<stdin>"), SCRIPT.to_string());
+        assert!(source.discover_check_functions(&LuaOptions::default(), "Check")?);
+        let lua = LuaOptions::default().new_lua()?;
+        source.load_into(&lua)?;
+        assert_eq!(lua.globals().get::<i64>("Limit").ok(), Some(3));
+
+        for conflict in ["--only", "--checks"] {
+            assert!(
+                Cli::try_parse_from(["unittest", "check", "--check-stdin", conflict, "x.lua"])
+                    .is_err(),
+                "--check-stdin should conflict with {conflict}"
+            );
+        }
+
+        // The check piped in is the only one run, even though the directory has a check file.
+        fs::write(
+            dir.as_path_untracked().join("script.lua"),
+            r#"function Check() return "from file" end"#,
+        )?;
+        fs::write(dir.as_path_untracked().join("data.json"), r#"{"max": 5}"#)?;
+        let report = dir.as_path_untracked().join("report.txt");
+        let cli = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--check-stdin",
+            "--report",
+            report.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .join("data.json")
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        let super::Command::Check(cmd) = cli.command else {
+            panic!("expected the check command");
+        };
+        let stdin = r#"function Check(obj) return "max is " .. obj.max end"#;
+        assert!(
+            cmd.run_with_stdin(stdin.as_bytes()).is_err(),
+            "expected the piped-in check to find an error"
+        );
+        let report = fs::read_to_string(&report)?;
+        assert!(report.contains("<stdin>: [Error] max is 5"), "{report}");
+        assert!(!report.contains("from file"), "{report}");
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_header_severity() -> Result<()> {
+        const SCRIPT: &str = r#"-- Checks that are only advisory.
+-- @severity: warning
+
+function Check()
+    return { "plain finding", { message = "explicit finding", severity = "error" } }
+end
+"#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(dir.as_path_untracked().join("data.json"), "{}")?;
+        let report = dir.as_path_untracked().join("report.txt");
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--report",
+            report.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the explicit error to fail");
+
+        let report = fs::read_to_string(&report)?;
+        assert!(report.contains("[Warning] plain finding"), "{report}");
+        assert!(report.contains("[Error] explicit finding"), "{report}");
+
+        // The invalid level is ignored, and only warned about once the check is discovered.
+        let mut source = SourceCode::new(
+            dir.as_path_untracked().join("bad.lua"),
+            "-- @severity: loud\nfunction Check() end\n".to_string(),
+        );
+        assert_eq!(source.header_severity, None);
+        assert!(!logs_contain("invalid @severity directive"));
+        assert!(source.discover_check_functions(&LuaOptions::default(), "Check")?);
+        assert!(logs_contain("invalid @severity directive"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_lint() -> Result<()> {
+        let dir = test_temp_dir!();
+        let write =
+            |name: &str, contents: &str| fs::write(dir.as_path_untracked().join(name), contents);
+        write(
+            "good.lua",
+            "function Check(obj) if obj.bad then return 'bad' end end",
+        )?;
+        write("library.lua", "return { helper = function() end }")?;
+        write("good_test.lua", "function TestNothing() end")?;
+        let loads = dir.as_path_untracked().join("loads.txt");
+        write(
+            "raw.lua",
+            &format!(
+                r#"
+                local f = assert(io.open({:?}, "a"))
+                f:write("loaded\n")
+                f:close()
+                function Check(obj, ctx)
+                    if #ctx.raw > 0 then return "unexpected raw contents" end
+                end
+                "#,
+                loads.to_str().wrap_err("non UTF-8 test dir")?,
+            ),
+        )?;
+        let dir_str = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from(["unittest", "lint", "--allow-unsafe", dir_str])
+            .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("well-formed checks should pass")?;
+        assert!(logs_contain("linted 3 check files: 0 malformed"));
+        assert_eq!(fs::read_to_string(&loads)?, "loaded\n");
+
+        write("broken.lua", "function Check(\n")?;
+        write("number.lua", "function Check() return 42 end")?;
+        write(
+            "nested.lua",
+            "function Check_Name(obj) return obj.metadata.name end",
+        )?;
+        let cmd = Cli::try_parse_from(["unittest", "lint", "--allow-unsafe", dir_str])
+            .wrap_err("failed to parse args")?;
+        let result = cmd.run();
+        assert_eq!(super::exit_code(&result), ExitCode::from(1));
+        assert!(logs_contain("linted 6 check files: 3 malformed"));
+        assert!(logs_contain("failed to load Lua source from"));
+        assert!(logs_contain("'Check' fails on an empty document"));
+        assert!(logs_contain("'Check_Name' fails on an empty document"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_format_override() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj, ctx)
+                return "format " .. ctx.format .. " " .. obj.kind
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::create_dir_all(dir.as_path_untracked().join("manifests"))?;
+        fs::create_dir_all(dir.as_path_untracked().join("other"))?;
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("manifests/a.cfg"),
+            "kind: Manifest\n",
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("other/b.cfg"),
+            r#"{"kind": "Other"}"#,
+        )?;
+        let dir = dir
+            .as_path_untracked()
+            .to_str()
+            .wrap_err("non UTF-8 test dir")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--data-ext",
+            "cfg=json",
+            "--format-override",
+            "manifests/*.cfg=yaml",
+            "--",
+            dir,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected findings");
+        assert!(logs_contain("format yaml Manifest"));
+        assert!(logs_contain("format json Other"));
+
+        for invalid in ["manifests/*.cfg", "=yaml", "[=yaml", "*.cfg=nope"] {
+            assert!(
+                Cli::try_parse_from(["unittest", "check", "--format-override", invalid]).is_err(),
+                "expected '{invalid}' to be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_override_skips_own_files() -> Result<()> {
+        let dir = test_temp_dir!();
+        for name in [
+            "data.txt",
+            ".env.local",
+            super::file::IGNORE_FILE_NAME,
+            super::config::CONFIG_FILE_NAME,
+            "script.lua",
+            "script_test.lua",
+        ] {
+            fs::write(dir.as_path_untracked().join(name), "")?;
+        }
+        let output = dir.as_path_untracked().join("data.list");
+
+        // There are no checks to run, so only the data files are listed.
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--list-data",
+            "--dotfiles",
+            "--format-override",
+            "**/*=yaml",
+            "--format-override",
+            ".*=yaml",
+            "--output",
+            output.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run().wrap_err("failed to list data")?;
+
+        let listed = fs::read_to_string(&output)?;
+        let path = |name: &str| dir.as_path_untracked().join(name).display().to_string();
+        // The configuration file is still TOML data by its extension, like without overrides.
+        assert_eq!(
+            listed,
+            format!(
+                "YAML:\n  {}\n  {}\nTOML:\n  {}\n",
+                path(".env.local"),
+                path("data.txt"),
+                path(super::config::CONFIG_FILE_NAME),
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_aggregate() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(docs, context)
+                if context.format ~= "aggregate" then
+                    return nil
+                end
+                local seen = {}
+                local findings = {}
+                for _, entry in ipairs(docs) do
+                    local name = entry.document.name
+                    local where = entry.path:match("[^/]+$") .. ":" .. entry.index
+                    if seen[name] then
+                        table.insert(findings, "duplicate name " .. name .. " in " .. where .. ", first in " .. seen[name])
+                    else
+                        seen[name] = where
+                    end
+                end
+                return findings
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("a.yaml"),
+            "name: alpha\n---\nname: beta\n",
+        )?;
+        fs::write(
+            dir.as_path_untracked().join("b.yaml"),
+            "name: gamma\n---\nname: alpha\n",
+        )?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        cmd.run()
+            .wrap_err("expected no findings without --aggregate")?;
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--aggregate",
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected the duplicate name to be found"
+        );
+        assert!(logs_contain(
+            "duplicate name alpha in b.yaml:1, first in a.yaml:0"
+        ));
+        assert!(logs_contain("<aggregate>"));
+        assert!(!logs_contain("duplicate name beta"));
+
+        let output = dir.as_path_untracked().join("output.txt");
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--aggregate",
+            "--format",
+            "github",
+            "--output",
+            output.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(
+            cmd.run().is_err(),
+            "expected the duplicate name to be found"
+        );
+        let output = fs::read_to_string(&output)?;
+        assert!(
+            output.starts_with("::error title=") && !output.contains("file="),
+            "unexpected output: {output}"
+        );
+
+        assert!(
+            Cli::try_parse_from(["unittest", "check", "--aggregate", "--fix"]).is_err(),
+            "expected --aggregate to conflict with --fix"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_check_repeat() -> Result<()> {
+        const SCRIPT: &str = r#"
+            function Check(obj)
+                if obj.bad then
+                    return "bad document"
+                end
+            end
+        "#;
+        let dir = test_temp_dir!();
+        fs::write(dir.as_path_untracked().join("script.lua"), SCRIPT)?;
+        fs::write(
+            dir.as_path_untracked().join("data.json"),
+            r#"{"bad": true}"#,
+        )?;
+        let report = dir.as_path_untracked().join("report.txt");
+
+        let cmd = Cli::try_parse_from([
+            "unittest",
+            "check",
+            "--repeat",
+            "3",
+            "--report",
+            report.to_str().wrap_err("non UTF-8 test dir")?,
+            "--",
+            dir.as_path_untracked()
+                .to_str()
+                .wrap_err("non UTF-8 test dir")?,
+        ])
+        .wrap_err("failed to parse args")?;
+        assert!(cmd.run().is_err(), "expected the finding to fail the run");
+        assert!(logs_contain("ran 3 times: min "));
+        let report = fs::read_to_string(&report)?;
+        assert_eq!(
+            report.matches("bad document").count(),
+            1,
+            "expected only the findings of the first run: {report}"
+        );
+
+        for args in [&["--repeat", "0"][..], &["--repeat", "2", "--fix"]] {
+            let args = ["unittest", "check"].iter().chain(args).collect::<Vec<_>>();
+            assert!(
+                Cli::try_parse_from(&args).is_err(),
+                "expected {args:?} to be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_dotfiles_and_dotdirs() -> Result<()> {
+        let dir = test_temp_dir!();
+        let root = dir.as_path_untracked();
+        fs::write(
+            root.join("script.lua"),
+            r#"function Check() return "checked" end"#,
+        )?;
+        for dir in ["visible", ".hidden"] {
+            fs::create_dir_all(root.join(dir))?;
+            fs::write(root.join(dir).join("data.json"), "{}")?;
+            fs::write(root.join(dir).join(".data.json"), "{}")?;
+        }
+        let report = root.join("report.txt");
+        let report_path = report.to_str().wrap_err("non UTF-8 test dir")?;
+        let config = root.join("skip-dotdirs.toml");
+        fs::write(&config, "dotdirs = false\n")?;
+        let config = config.to_str().wrap_err("non UTF-8 test dir")?;
+        let root = root.to_str().wrap_err("non UTF-8 test dir")?;
+
+        let checked = |flags: &[&str]| -> Result<Vec<&str>> {
+            let mut args = vec!["unittest", "check", "--report", report_path];
+            args.extend(flags);
+            args.extend(["--", root]);
+            let cmd = Cli::try_parse_from(args).wrap_err("failed to parse args")?;
+            assert!(cmd.run().is_err(), "expected findings with {flags:?}");
+            let report = fs::read_to_string(&report)?;
+            Ok([
+                ".hidden/.data.json",
+                ".hidden/data.json",
+                "visible/.data.json",
+                "visible/data.json",
+            ]
+            .into_iter()
+            .filter(|file| report.contains(&format!("{file}: ")))
+            .collect())
+        };
+
+        assert_eq!(checked(&[])?, [".hidden/data.json", "visible/data.json"]);
+        assert_eq!(checked(&["--dotdirs=false"])?, ["visible/data.json"]);
+        assert_eq!(
+            checked(&["--dotfiles", "--dotdirs=false"])?,
+            ["visible/.data.json", "visible/data.json"]
+        );
+        // The flag takes precedence over the configuration.
+        assert_eq!(checked(&["--config", config])?, ["visible/data.json"]);
+        assert_eq!(
+            checked(&["--config", config, "--dotdirs"])?,
+            [".hidden/data.json", "visible/data.json"]
+        );
+        assert_eq!(
+            checked(&["--dotfiles", "--dotdirs"])?,
+            [
+                ".hidden/.data.json",
+                ".hidden/data.json",
+                "visible/.data.json",
+                "visible/data.json"
+            ]
+        );
+
+        Ok(())
+    }
 }
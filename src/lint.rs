@@ -0,0 +1,165 @@
+use crate::{
+    FindingsError,
+    check::{create_context, plural},
+    config::Config,
+    file::{FileSearchResult, FileSearcher, expand_globs},
+    lua::{LoadError, LuaOptions, SourceCode},
+};
+use clap::Args;
+use eyre::{Context, ContextCompat, Result, ensure, eyre};
+use globset::Glob;
+use rayon::prelude::*;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{debug, error, info};
+
+#[derive(Debug, Args)]
+pub struct Lint {
+    /// The check files or directories to lint.
+    ///
+    /// Files starting with a period (`.`) are ignored by default, as are `_test.lua` files.
+    /// Directories starting with a period are walked, unless `--dotdirs=false` is given.
+    ///
+    /// Glob patterns (e.g. `checks/**/*.lua`) are expanded, even if the shell doesn't.
+    #[arg(default_value = ".")]
+    input: Vec<PathBuf>,
+
+    /// Enable processing of files starting with a period.
+    ///
+    /// Pass `--dotfiles=false` to turn this off when the configuration enables it.
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    dotfiles: Option<bool>,
+
+    /// Whether to descend into directories starting with a period, e.g. `.github`. They are by
+    /// default; pass `--dotdirs=false` to skip them, e.g. to stay out of `.git`.
+    ///
+    /// This is independent of `--dotfiles`: files starting with a period are still ignored within
+    /// these directories, unless `--dotfiles` is given as well.
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    dotdirs: Option<bool>,
+
+    /// Skip files and directories matching this glob, e.g. `vendor/**` or `*.generated.json`.
+    ///
+    /// The patterns are matched as if they were in a `.checkonautignore` file in every input:
+    /// against the path within the input, and against the plain file name.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<Glob>,
+
+    /// The name of the check function, as with `check --check-fn`.
+    #[arg(long, default_value = "Check")]
+    check_fn: String,
+
+    #[command(flatten)]
+    lua: LuaOptions,
+}
+
+impl Lint {
+    /// Fills in the flags that weren't given on the command line from the configuration.
+    pub fn apply_config(&mut self, config: &Config) -> Result<()> {
+        self.dotfiles = self.dotfiles.or(config.dotfiles);
+        self.dotdirs = self.dotdirs.or(config.dotdirs);
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude_globs()?;
+        }
+        self.lua.apply_config(config);
+        Ok(())
+    }
+
+    pub fn run(self) -> Result<()> {
+        self.lua.report();
+        let input = expand_globs(self.input).wrap_err("failed to expand input paths")?;
+        let FileSearchResult {
+            check_files,
+            test_files: _,
+            data_files: _,
+        } = FileSearcher::default()
+            .include_dotfiles(self.dotfiles.unwrap_or(false))
+            .include_dotdirs(self.dotdirs.unwrap_or(true))
+            .exclude(self.exclude.clone())
+            .include_check_files(true)
+            .search(input.into_par_iter())
+            .wrap_err("failed to search input paths for relevant files")?;
+
+        let mut results = check_files
+            .into_par_iter()
+            .map(|file| {
+                let problems = lint_file(&file, &self.lua, &self.check_fn)
+                    .wrap_err_with(|| format!("while linting file {}", file.display()))?;
+                Ok((file, problems))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        results.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut malformed = 0;
+        for (file, problems) in &results {
+            if !problems.is_empty() {
+                malformed += 1;
+            }
+            for problem in problems {
+                error!(path = %file.display(), %problem, "malformed check file");
+            }
+        }
+        info!(
+            files = results.len(),
+            malformed,
+            "linted {}: {} malformed",
+            plural(results.len(), "check file"),
+            malformed,
+        );
+        ensure!(
+            malformed == 0,
+            FindingsError("one or more check files are malformed".to_string())
+        );
+        Ok(())
+    }
+}
+
+/// Finds the problems of a check file: that it fails to load, or that any of its check functions
+/// fails on an empty document or returns something that isn't a finding.
+///
+/// Files without a check function are libraries, as with `check`, so they have no problems as long
+/// as they load.
+fn lint_file(path: &Path, options: &LuaOptions, check_fn: &str) -> Result<Vec<String>> {
+    let mut source = SourceCode::read(path)?;
+    match source.discover_check_functions(options, check_fn) {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!(path = %path.display(), "no check function; assuming it's a library");
+            return Ok(Vec::new());
+        }
+        Err(e) if e.is::<LoadError>() => return Ok(vec![format!("{e:#}")]),
+        Err(e) => return Err(e),
+    }
+
+    // The source was just loaded to discover its check functions, so it's not loaded again.
+    let lua = source
+        .take_loaded()
+        .wrap_err("check file was not loaded during discovery")?;
+    let document = mlua::Value::Table(
+        lua.create_table()
+            .map_err(|e| eyre!("failed to create empty document: {e}"))?,
+    );
+    let raw = Arc::from([]);
+    let context = create_context(&lua, None, Path::new("<lint>"), "json", Some(&raw), &source)?;
+    let mut problems = Vec::new();
+    for function in source.check_functions() {
+        if let Err(e) = source.call_check_function(&lua, function, &document, None, &context) {
+            problems.push(format!("'{function}' fails on an empty document: {e:#}"));
+        }
+    }
+    Ok(problems)
+}
@@ -1,91 +1,431 @@
+use crate::{CancellationToken, config::Config, file::retry_io};
+use base64::Engine as _;
+use clap::Args;
 use eyre::{Context, ContextCompat, Result, bail, eyre};
-use mlua::{FromLua, Function, Lua, LuaSerdeExt};
+use mlua::{FromLua, Function, Lua, LuaSerdeExt, StdLib};
+use serde::{Deserialize, Serialize};
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     fmt,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
 };
-use tracing::debug;
+use tracing::{debug, warn};
+
+/// Options for the Lua environment that checks and tests run in.
+#[derive(Debug, Clone, Default, Args)]
+pub struct LuaOptions {
+    /// Pin the time returned by `Now()` in Lua, for deterministic runs.
+    ///
+    /// This is either a Unix timestamp in seconds, or an RFC 3339 date and time.
+    #[arg(long, env = "CHECKONAUT_NOW", value_parser = parse_now)]
+    pub now: Option<i64>,
+
+    /// Additional directories to search for Lua modules when `require`-ing them.
+    ///
+    /// These are searched after the directory of the script itself.
+    #[arg(long)]
+    pub lua_path: Vec<PathBuf>,
+
+    /// Allow Lua to use the `os` and `io` standard libraries.
+    ///
+    /// These are disabled by default, as they let checks run commands and read or write any file.
+    #[arg(long)]
+    pub allow_unsafe: bool,
+
+    /// Seed Lua's `math.random` with this, for reproducible runs.
+    ///
+    /// By default, every Lua state is seeded randomly.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Allow Lua to read this environment variable with `Env(name)`.
+    ///
+    /// Other environment variables read as nil.
+    #[arg(long, value_name = "NAME")]
+    pub expose_env: Vec<String>,
+
+    /// A Lua file to load before every check and test file, e.g. to define shared helpers.
+    ///
+    /// This is loaded after the `checkonaut` module is registered, so it can use it.
+    #[arg(long, value_name = "PATH", value_parser = parse_prelude)]
+    pub prelude: Option<SourceCode>,
+
+    /// Aborts running Lua code once cancelled. Without it, nothing can cancel the Lua states.
+    #[arg(skip)]
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl LuaOptions {
+    /// Fills in the flags that weren't given on the command line from the configuration.
+    pub fn apply_config(&mut self, config: &Config) {
+        if self.lua_path.is_empty() {
+            self.lua_path = config.lua_path.clone();
+        }
+    }
+
+    /// Whether the run has been cancelled with the [`Self::cancellation`] token, if any.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        (self.cancellation.as_ref()).is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Creates a new Lua state configured by these options.
+    pub fn new_lua(&self) -> Result<Lua> {
+        let libs = if self.allow_unsafe {
+            StdLib::ALL_SAFE
+        } else {
+            StdLib::ALL_SAFE ^ (StdLib::OS | StdLib::IO)
+        };
+        let lua = Lua::new_with(libs, mlua::LuaOptions::default())
+            .map_err(|e| eyre!("failed to create Lua state: {e}"))?;
+        if let Some(seed) = self.seed {
+            let math = lua
+                .globals()
+                .get::<mlua::Table>("math")
+                .map_err(|e| eyre!("failed to get math table in Lua: {e}"))?;
+            math.get::<Function>("randomseed")
+                .and_then(|f| f.call::<()>(seed as i64))
+                .map_err(|e| eyre!("failed to seed Lua randomness: {e}"))?;
+        }
+        // Abort running Lua code once the run is cancelled, so slow checks don't hold it up.
+        if let Some(token) = self.cancellation.clone() {
+            lua.set_global_hook(
+                mlua::HookTriggers::new().every_nth_instruction(1024),
+                move |_, _| {
+                    if token.is_cancelled() {
+                        return Err(mlua::Error::runtime("interrupted"));
+                    }
+                    Ok(mlua::VmState::Continue)
+                },
+            )
+            .map_err(|e| eyre!("failed to set Lua cancellation hook: {e}"))?;
+        }
+        lua.set_app_data(self.clone());
+        Ok(lua)
+    }
+
+    /// Logs the options that affect every Lua state, and warns about any `--lua-path` directories
+    /// that don't exist.
+    pub fn report(&self) {
+        if let Some(seed) = self.seed {
+            debug!(seed, "seeding Lua randomness");
+        }
+        for dir in self.lua_path.iter().filter(|d| !d.is_dir()) {
+            warn!(path = %dir.display(), "Lua path directory does not exist");
+        }
+    }
+}
+
+fn parse_now(s: &str) -> Result<i64, String> {
+    if let Ok(timestamp) = s.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|d| d.timestamp())
+        .map_err(|e| format!("expected a Unix timestamp or RFC 3339 date and time: {e}"))
+}
+
+/// Finds the `-- @severity: <level>` directive in the first lines of the source code, e.g.
+/// `-- @severity: warning`.
+///
+/// Only the comment lines at the top of the file are searched. The level is returned as written,
+/// so an invalid one can be warned about.
+fn header_severity(contents: &str) -> Option<&str> {
+    const MAX_LINES: usize = 10;
+
+    let comments = (contents.lines().take(MAX_LINES))
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map_while(|line| line.strip_prefix("--"));
+    for comment in comments {
+        let Some(value) = comment.trim().strip_prefix("@severity:") else {
+            continue;
+        };
+        return Some(value.trim());
+    }
+    None
+}
+
+fn parse_prelude(s: &str) -> Result<SourceCode, String> {
+    SourceCode::read(s).map_err(|e| format!("{e:#}"))
+}
+
+/// The error of Lua source code that doesn't compile, or errors when run to define its functions.
+///
+/// This is the fault of the source file itself, rather than of checkonaut or its options.
+#[derive(Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    message: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to load Lua source from '{}': {}",
+            self.path.display(),
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for LoadError {}
 
 #[derive(Debug, Clone)]
 pub struct SourceCode {
     pub path: PathBuf,
     name: String,
     contents: String,
+    /// The check functions found by [`Self::discover_check_functions`].
+    check_functions: Vec<String>,
+    /// The severity of findings that don't give one, from a `-- @severity: <level>` comment.
+    pub(crate) header_severity: Option<CheckSeverity>,
+    /// The Lua state the source was loaded into by [`Self::discover_check_functions`], until it's
+    /// taken with [`Self::take_loaded`].
+    loaded: Arc<Mutex<Option<Lua>>>,
 }
 
 impl SourceCode {
+    /// The source code itself.
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+
+    /// The check functions found by the last call to [`Self::discover_check_functions`].
+    pub fn check_functions(&self) -> &[String] {
+        &self.check_functions
+    }
+
     pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read_with_retries(path, 0)
+    }
+
+    /// Like [`SourceCode::read`], but retries transient IO errors, as with [`retry_io`].
+    pub fn read_with_retries(path: impl AsRef<Path>, retries: u32) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
-        let name = format!("@{}", path.to_string_lossy());
-        let contents = std::fs::read_to_string(&path)
+        let contents = retry_io(retries, || std::fs::read_to_string(&path))
             .wrap_err_with(|| format!("failed to read source file: {}", path.display()))?;
-        Ok(Self {
+        Ok(Self::new(path, contents))
+    }
+
+    /// Source code that wasn't read from the path, e.g. from stdin.
+    ///
+    /// The path is still used to resolve `require` and relative file reads, as if the source code
+    /// had been read from it.
+    pub fn new(path: PathBuf, contents: String) -> Self {
+        let name = format!("@{}", path.to_string_lossy());
+        let header_severity = header_severity(&contents).and_then(CheckSeverity::parse);
+        Self {
             path,
             name,
             contents,
-        })
+            check_functions: Vec::new(),
+            header_severity,
+            loaded: Arc::default(),
+        }
     }
 
     pub fn load_into(&self, to: &Lua) -> Result<()> {
+        self.load(to, false)
+    }
+
+    /// Like [`SourceCode::load_into`], but also makes the test helpers available as
+    /// `checkonaut.test`, or with `require("@checkonaut.test")`.
+    pub fn load_test_into(&self, to: &Lua) -> Result<()> {
+        self.load(to, true)
+    }
+
+    fn load(&self, to: &Lua, with_test_module: bool) -> Result<()> {
         update_package_path(to, &self.path)?;
-        self.checkonaut_module(to)
+        // The Lua state may be shared between check files, so don't let a default severity set by
+        // one leak into the next.
+        to.remove_app_data::<DefaultSeverity>();
+        let module = self
+            .checkonaut_module(to)
             .wrap_err("failed to load 'checkonaut' module")?;
+        if with_test_module {
+            self.test_module(to, &module)
+                .wrap_err("failed to load 'checkonaut.test' module")?;
+        }
+        let prelude = to
+            .app_data_ref::<LuaOptions>()
+            .and_then(|o| o.prelude.clone());
+        if let Some(prelude) = prelude {
+            to.load(&prelude.contents)
+                .set_name(&prelude.name)
+                .exec()
+                .map_err(|e| {
+                    eyre!(
+                        "failed to load prelude '{}': {e}{}",
+                        prelude.path.display(),
+                        prelude.snippet(&e.to_string()),
+                    )
+                })?;
+        }
         to.load(&self.contents)
             .set_name(&self.name)
             .exec()
-            .map_err(|e| {
-                eyre!(
-                    "failed to load Lua source from '{}': {e}",
-                    self.path.display(),
-                )
+            .map_err(|e| LoadError {
+                path: self.path.clone(),
+                message: format!("{e}{}", self.snippet(&e.to_string())),
             })?;
         Ok(())
     }
 
-    pub fn has_check_function(&self) -> Result<bool> {
-        let lua = new_lua_for(&self.path)?;
-        self.checkonaut_module(&lua)
-            .wrap_err("failed to load 'checkonaut' module")?;
+    /// The line of this source code that a Lua error message points at, to append to the message.
+    ///
+    /// If the message names the token it failed near, and that token appears once on the line, a
+    /// caret is put under it. This is empty if the message doesn't point into this source code.
+    fn snippet(&self, message: &str) -> String {
+        let Some(path) = self.name.strip_prefix('@') else {
+            return String::new();
+        };
+        // Lua shortens long chunk names to their end, prefixed with "...".
+        let line_number = message.match_indices(':').find_map(|(i, _)| {
+            let rest = &message[i + 1..];
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 || !rest[digits..].starts_with(':') {
+                return None;
+            }
+            let start = message[..i].rfind(char::is_whitespace).map_or(0, |s| s + 1);
+            let chunk = message[start..i].trim_start_matches("...");
+            if chunk.is_empty() || !path.ends_with(chunk) {
+                return None;
+            }
+            rest[..digits].parse::<usize>().ok()
+        });
+        let Some((line_number, line)) = line_number.and_then(|n| {
+            let line = self.contents.lines().nth(n.checked_sub(1)?)?;
+            Some((n, line.trim()))
+        }) else {
+            return String::new();
+        };
+        let gutter = line_number.to_string();
+        let mut snippet = format!("\n{gutter} | {line}");
+        let token = message
+            .split_once("near '")
+            .and_then(|(_, rest)| rest.split_once('\''))
+            .map(|(token, _)| token)
+            .filter(|token| !token.is_empty() && line.matches(token).count() == 1);
+        if let Some(column) = token.and_then(|token| line.find(token)) {
+            let padding = " ".repeat(line[..column].chars().count());
+            snippet.push_str(&format!("\n{} | {padding}^", " ".repeat(gutter.len())));
+        }
+        snippet
+    }
+
+    /// Finds the check functions for the given name, as found by [`Self::check_function_names`],
+    /// and remembers them for [`Self::check_functions`]. Returns whether there are any.
+    ///
+    /// The source is loaded into a Lua state of its own, which is kept for [`Self::take_loaded`],
+    /// so the first run of the check doesn't need to load it again.
+    pub fn discover_check_functions(
+        &mut self,
+        options: &LuaOptions,
+        function_name: &str,
+    ) -> Result<bool> {
+        if let Some(value) =
+            header_severity(&self.contents).filter(|v| CheckSeverity::parse(v).is_none())
+        {
+            warn!(
+                path = %self.path.display(),
+                value,
+                "invalid @severity directive; expected fatal, error, or warning",
+            );
+        }
+        let lua = options.new_lua()?;
         self.load_into(&lua)?;
+        self.check_functions = Self::check_function_names(&lua, function_name)?;
+        self.loaded = Arc::new(Mutex::new(Some(lua)));
+        Ok(!self.check_functions.is_empty())
+    }
 
-        match lua.globals().get::<mlua::Function>("Check") {
-            Ok(_) => Ok(true),
-            Err(mlua::Error::FromLuaConversionError { .. }) => Ok(false),
-            Err(e) => Err(eyre!("failed to check for 'Check' function: {e}")),
+    /// Takes the Lua state the source was loaded into by [`Self::discover_check_functions`], if
+    /// it hasn't been taken yet.
+    ///
+    /// The state is only handed out once, as checks may keep state in their globals.
+    pub fn take_loaded(&self) -> Option<Lua> {
+        (self.loaded.lock().unwrap_or_else(PoisonError::into_inner)).take()
+    }
+
+    /// The names of the global check functions for the given name, in sorted order.
+    ///
+    /// These are the function with the name itself (e.g. `Check`), and any function whose name
+    /// starts with the name and an underscore (e.g. `Check_Naming`), so one file can hold several
+    /// related checks.
+    pub fn check_function_names(lua: &Lua, function_name: &str) -> Result<Vec<String>> {
+        let prefix = format!("{function_name}_");
+        let mut names = Vec::new();
+        for pair in lua.globals().pairs::<mlua::Value, mlua::Value>() {
+            let (k, v) = pair.map_err(|e| eyre!("failed to iterate over Lua globals: {e}"))?;
+            let Some(k) = k.as_string().filter(|_| v.is_function()) else {
+                continue;
+            };
+            let k = k.to_string_lossy();
+            if k == function_name || k.starts_with(&prefix) {
+                names.push(k);
+            }
         }
+        names.sort_unstable();
+        Ok(names)
     }
 
-    /// Calls the `Check` function defined in the source code.
+    /// Calls the check function (typically `Check`) defined in the source code.
     ///
     /// You should call `load_into` before calling this function, otherwise there is no `Check`.
-    /// You should only call this function if [`Self::has_check_function`] returns `true`.
+    /// The function name should be one of [`Self::check_functions`].
+    ///
+    /// The `document_index` is attached to every returned error, and should be given when the
+    /// document came from a file holding more than one document.
     pub fn call_check_function(
         &self,
         lua: &Lua,
+        function_name: &str,
         document: &mlua::Value,
+        document_index: Option<usize>,
         context: &mlua::Value,
     ) -> Result<Vec<CheckError>> {
         let check_fn: Function = lua
             .globals()
-            .get("Check")
-            .map_err(|e| eyre!("failed to find 'Check' function in Lua state: {e}"))?;
-        let result: CheckResult = check_fn
-            .call((document, context))
-            .map_err(|e| eyre!("could not call 'Check' function: {e}"))?;
-        Ok(result.flatten())
+            .get(function_name)
+            .map_err(|e| eyre!("failed to find '{function_name}' function in Lua state: {e}"))?;
+        let result: CheckResult = check_fn.call((document, context)).map_err(|e| match e {
+            mlua::Error::MemoryError(e) => {
+                eyre!("'{function_name}' function exceeded the Lua memory limit: {e}")
+            }
+            e => eyre!(
+                "could not call '{function_name}' function: {e}{}",
+                self.snippet(&e.to_string())
+            ),
+        })?;
+        // `SetDefaultSeverity` is more specific than the header, as it may be called conditionally.
+        let default_severity = (lua.app_data_ref::<DefaultSeverity>().map(|s| s.0))
+            .or(self.header_severity)
+            .unwrap_or(CheckSeverity::Error);
+        let mut errors = result.flatten(default_severity);
+        for error in &mut errors {
+            error.document_index = document_index;
+        }
+        Ok(errors)
     }
 
     /// Calls all `Test` functions defined in the source code.
     ///
     /// You should call `load_into` before calling this function, otherwise there are no `Test`
-    /// functions.
-    pub fn call_test_functions(&self, lua: &Lua) -> Result<Vec<String>> {
+    /// functions. Only test functions whose name matches the filter are called.
+    pub fn call_test_functions(
+        &self,
+        lua: &Lua,
+        filter: impl Fn(&str) -> bool,
+    ) -> Result<TestOutcome> {
         let fln = self
             .path
             .file_name()
             .map(|s| s.display())
             .wrap_err("failed to find file name for test source code")?;
-        let mut results = Vec::new();
+        let mut outcome = TestOutcome::default();
         for pair in lua.globals().pairs::<mlua::Value, mlua::Value>() {
             let (k, v) = pair.map_err(|e| eyre!("failed to iterate over Lua globals: {e}"))?;
             let Some(v) = v.as_function() else { continue };
@@ -95,9 +435,13 @@ impl SourceCode {
             if !k.to_string_lossy().starts_with("Test") {
                 continue;
             }
+            if !filter(&k.to_string_lossy()) {
+                debug!(file = %fln, test = %k.display(), "skipping test not matching filter");
+                continue;
+            }
 
             match v.call::<mlua::Value>(()) {
-                Ok(mlua::Value::Nil) => {}
+                Ok(mlua::Value::Nil) => outcome.passed += 1,
                 Ok(val) => {
                     let json: serde_json::Value = lua.from_value(val).map_err(|e| {
                         eyre!(
@@ -108,107 +452,948 @@ impl SourceCode {
                     })?;
                     let json = serde_json::to_string(&json)
                         .wrap_err("failed to convert serde_json::Value to string")?;
-                    results.push(format!("{fln}/{}: {json}", k.to_string_lossy()));
-                }
-                Err(mlua::Error::RuntimeError(e)) => {
-                    results.push(format!("{fln}/{}: {e}", k.to_string_lossy()));
-                }
-                Err(e) => {
-                    bail!(
-                        "failed to call test function '{}': {}",
-                        k.to_string_lossy(),
-                        e
-                    );
+                    outcome
+                        .failures
+                        .push(format!("{fln}/{}: {json}", k.to_string_lossy()));
                 }
+                // Errors raised by our own helpers (e.g. assertions) are wrapped in a callback
+                // error, but are still test failures.
+                Err(e) => match runtime_error(&e) {
+                    Some(e) => {
+                        outcome
+                            .failures
+                            .push(format!("{fln}/{}: {e}", k.to_string_lossy()));
+                    }
+                    None => {
+                        bail!(
+                            "failed to call test function '{}': {}",
+                            k.to_string_lossy(),
+                            e
+                        );
+                    }
+                },
             }
         }
-        Ok(results)
+        Ok(outcome)
     }
 
-    fn checkonaut_module(&self, lua: &Lua) -> Result<()> {
+    /// The functions of the `checkonaut.test` module, as listed by `checkonaut explain`.
+    ///
+    /// Keep this in sync with the functions registered by [`Self::test_module`];
+    /// `test_builtins_documented` fails otherwise.
+    pub const TEST_BUILTINS: &[Builtin] = &[
+        Builtin {
+            name: "AssertEqual",
+            signature: "AssertEqual(actual, expected)",
+            description: "Fails the test unless the values are equal, as compared by DeepEqual.",
+        },
+        Builtin {
+            name: "AssertContains",
+            signature: "AssertContains(sequence, value)",
+            description: "Fails the test unless the sequence contains the value.",
+        },
+        Builtin {
+            name: "AssertError",
+            signature: "AssertError(function)",
+            description: "Fails the test unless calling the function raises an error.",
+        },
+    ];
+
+    /// The functions of the `checkonaut` module, as listed by `checkonaut explain`.
+    ///
+    /// Keep this in sync with the functions registered by [`Self::checkonaut_module`];
+    /// `test_builtins_documented` fails otherwise.
+    pub const BUILTINS: &[Builtin] = &[
+        Builtin {
+            name: "ReadJSON",
+            signature: "ReadJSON(path) -> value",
+            description: "Reads and parses a JSON file, relative to the check file.",
+        },
+        Builtin {
+            name: "ReadFile",
+            signature: "ReadFile(path, max_bytes?) -> string",
+            description: "Reads a file as-is, relative to the check file; up to 16 MiB by default.",
+        },
+        Builtin {
+            name: "Matches",
+            signature: "Matches(str, pattern | {pattern}, flags?) -> boolean",
+            description: "Whether the string matches the regex, or any of them; flags like \"im\".",
+        },
+        Builtin {
+            name: "Query",
+            signature: "Query(document, path) -> value",
+            description: "Queries the document with JSONPath: nil, one value, or a list of values.",
+        },
+        Builtin {
+            name: "ValidateSchema",
+            signature: "ValidateSchema(document, schema_path) -> {string}",
+            description: "Validates the document against a JSON schema file, returning the errors.",
+        },
+        Builtin {
+            name: "SetDefaultSeverity",
+            signature: "SetDefaultSeverity(severity)",
+            description: "Sets the severity of findings that don't give one, e.g. \"warning\".",
+        },
+        Builtin {
+            name: "Split",
+            signature: "Split(str, delimiter) -> {string}",
+            description: "Splits the string; an empty delimiter splits after each character.",
+        },
+        Builtin {
+            name: "TrimSpace",
+            signature: "TrimSpace(str) -> string",
+            description: "Removes leading and trailing whitespace.",
+        },
+        Builtin {
+            name: "StartsWith",
+            signature: "StartsWith(str, prefix) -> boolean",
+            description: "Whether the string starts with the prefix.",
+        },
+        Builtin {
+            name: "EndsWith",
+            signature: "EndsWith(str, suffix) -> boolean",
+            description: "Whether the string ends with the suffix.",
+        },
+        Builtin {
+            name: "Now",
+            signature: "Now() -> integer",
+            description: "The current Unix timestamp, or the one given with --now.",
+        },
+        Builtin {
+            name: "ParseDate",
+            signature: "ParseDate(str, format?) -> integer?",
+            description: "Parses a date as a Unix timestamp; RFC 3339 unless a format is given.",
+        },
+        Builtin {
+            name: "DeepEqual",
+            signature: "DeepEqual(a, b) -> boolean",
+            description: "Whether the values are equal when converted to JSON.",
+        },
+        Builtin {
+            name: "Contains",
+            signature: "Contains(sequence, value) -> boolean",
+            description: "Whether the sequence contains the value, as compared by DeepEqual.",
+        },
+        Builtin {
+            name: "IsInteger",
+            signature: "IsInteger(value) -> boolean",
+            description: "Whether the value is a number without a fractional part, e.g. 2 or 2.0.",
+        },
+        Builtin {
+            name: "AsNumber",
+            signature: "AsNumber(value) -> number?",
+            description: "The number, or the number in a numeric string; nil for anything else.",
+        },
+        Builtin {
+            name: "Flatten",
+            signature: "Flatten(table) -> table",
+            description: "Flattens nested tables into dotted paths, e.g. a.0.b; see the README.",
+        },
+        Builtin {
+            name: "Keys",
+            signature: "Keys(table) -> {string}",
+            description: "The string keys of the table, sorted.",
+        },
+        Builtin {
+            name: "Values",
+            signature: "Values(table) -> {value}",
+            description: "The values of the table's string keys, in the order of Keys.",
+        },
+        Builtin {
+            name: "Log",
+            signature: "Log(message, value?)",
+            description: "Logs a message at debug level, for debugging checks.",
+        },
+        Builtin {
+            name: "Error",
+            signature: "Error(message, fields?) -> finding",
+            description: "Creates a finding of severity error, with any extra fields.",
+        },
+        Builtin {
+            name: "Warn",
+            signature: "Warn(message, fields?) -> finding",
+            description: "Creates a finding of severity warning, with any extra fields.",
+        },
+        Builtin {
+            name: "Env",
+            signature: "Env(name) -> string?",
+            description: "Reads an environment variable allowed with --expose-env.",
+        },
+        Builtin {
+            name: "Base64Encode",
+            signature: "Base64Encode(str, url_safe?) -> string",
+            description: "Encodes the string as base64.",
+        },
+        Builtin {
+            name: "Base64Decode",
+            signature: "Base64Decode(str, url_safe?) -> string",
+            description: "Decodes base64 into a string.",
+        },
+        Builtin {
+            name: "SemverSatisfies",
+            signature: "SemverSatisfies(version, requirement) -> boolean",
+            description: "Whether the semantic version matches the requirement, e.g. \">=1.2\".",
+        },
+        Builtin {
+            name: "SemverCompare",
+            signature: "SemverCompare(a, b) -> integer",
+            description: "Compares semantic versions: -1, 0, or 1.",
+        },
+    ];
+
+    /// Registers the helpers for test files, both as `@checkonaut.test` and as the `test` field of
+    /// the given `checkonaut` module.
+    fn test_module(&self, lua: &Lua, checkonaut: &mlua::Table) -> Result<()> {
+        fn to_json(l: &Lua, value: mlua::Value) -> mlua::Result<String> {
+            let json: serde_json::Value = l.from_value(value)?;
+            Ok(json.to_string())
+        }
+
+        // Values are compared like with `DeepEqual`.
+        let assert_equal = lua
+            .create_function(|l, (actual, expected): (mlua::Value, mlua::Value)| {
+                let actual: serde_json::Value = l.from_value(actual)?;
+                let expected: serde_json::Value = l.from_value(expected)?;
+                if actual != expected {
+                    return Err(mlua::Error::runtime(format!(
+                        "AssertEqual failed: expected {expected}, got {actual}"
+                    )));
+                }
+                Ok(())
+            })
+            .map_err(|e| eyre!("failed to create assert_equal function: {e}"))?;
+
+        let assert_contains = lua
+            .create_function(|l, (haystack, needle): (mlua::Value, mlua::Value)| {
+                let haystack: serde_json::Value = l.from_value(haystack)?;
+                let needle: serde_json::Value = l.from_value(needle)?;
+                let found = match &haystack {
+                    serde_json::Value::Array(values) => values.contains(&needle),
+                    serde_json::Value::Object(values) if values.is_empty() => false,
+                    _ => {
+                        return Err(mlua::Error::runtime(format!(
+                            "AssertContains failed: expected a sequence table, got {haystack}"
+                        )));
+                    }
+                };
+                if !found {
+                    return Err(mlua::Error::runtime(format!(
+                        "AssertContains failed: expected {haystack} to contain {needle}"
+                    )));
+                }
+                Ok(())
+            })
+            .map_err(|e| eyre!("failed to create assert_contains function: {e}"))?;
+
+        let assert_error = lua
+            .create_function(
+                |l, function: Function| match function.call::<mlua::Value>(()) {
+                    Ok(value) => Err(mlua::Error::runtime(format!(
+                        "AssertError failed: expected an error, but the function returned {}",
+                        to_json(l, value)?
+                    ))),
+                    Err(_) => Ok(()),
+                },
+            )
+            .map_err(|e| eyre!("failed to create assert_error function: {e}"))?;
+
+        let module = lua
+            .create_table_from([
+                ("AssertEqual", assert_equal),
+                ("AssertContains", assert_contains),
+                ("AssertError", assert_error),
+            ])
+            .map_err(|e| eyre!("failed to create table for test module: {e}"))?;
+        checkonaut
+            .set("test", &module)
+            .map_err(|e| eyre!("failed to add test module to checkonaut module: {e}"))?;
+        lua.register_module("@checkonaut.test", module)
+            .map_err(|e| eyre!("failed to register checkonaut test module: {e}"))?;
+        debug!(
+            "loaded 'checkonaut.test' module for '{}'",
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    fn checkonaut_module(&self, lua: &Lua) -> Result<mlua::Table> {
         let source_path = self.path.clone();
 
         let read_json = lua
-            .create_function(move |l, path: mlua::String| {
-                let path_str = path.to_str()?;
-                let parent = source_path.parent().ok_or_else(|| {
-                    mlua::Error::runtime(format!(
-                        "cannot determine parent directory of '{}'",
-                        source_path.display(),
-                    ))
-                })?;
-                let full_path = parent.join(path_str.to_string());
-                let contents = std::fs::read_to_string(&full_path).map_err(|e| {
-                    mlua::Error::runtime(format!("failed to read '{}': {}", full_path.display(), e))
-                })?;
-                let json: serde_json::Value = serde_json::from_str(&contents).map_err(|e| {
-                    mlua::Error::runtime(format!(
-                        "failed to parse JSON in '{}': {}",
-                        full_path.display(),
-                        e
-                    ))
-                })?;
-                let value = l.to_value(&json)?;
-                Ok(value)
+            .create_function({
+                let source_path = source_path.clone();
+                move |l, path: mlua::String| {
+                    let (full_path, contents) = read_relative_file(&source_path, &path.to_str()?)?;
+                    let json: serde_json::Value = serde_json::from_str(&contents).map_err(|e| {
+                        mlua::Error::runtime(format!(
+                            "failed to parse JSON in '{}': {}",
+                            full_path.display(),
+                            e
+                        ))
+                    })?;
+                    let value = l.to_value(&json)?;
+                    Ok(value)
+                }
             })
             .map_err(|e| eyre!("failed to create read_json function: {e}"))?;
 
+        // The contents are returned as-is, so they need not be valid UTF-8.
+        let read_file = lua
+            .create_function({
+                let source_path = source_path.clone();
+                move |l, (path, max_bytes): (mlua::String, Option<u64>)| {
+                    let full_path = resolve_relative_path(&source_path, &path.to_str()?)?;
+                    let max_bytes = max_bytes.unwrap_or(READ_FILE_LIMIT);
+                    let read_error = |e: std::io::Error| {
+                        mlua::Error::runtime(format!(
+                            "failed to read '{}': {e}",
+                            full_path.display()
+                        ))
+                    };
+                    let size = std::fs::metadata(&full_path).map_err(read_error)?.len();
+                    if size > max_bytes {
+                        return Err(mlua::Error::runtime(format!(
+                            "'{}' is {size} bytes, more than the limit of {max_bytes} bytes",
+                            full_path.display()
+                        )));
+                    }
+                    l.create_string(std::fs::read(&full_path).map_err(read_error)?)
+                }
+            })
+            .map_err(|e| eyre!("failed to create read_file function: {e}"))?;
+
+        let validate_schema = lua
+            .create_function({
+                let source_path = source_path.clone();
+                move |l, (document, schema_path): (mlua::Value, mlua::String)| {
+                    let (full_path, contents) =
+                        read_relative_file(&source_path, &schema_path.to_str()?)?;
+                    let schema: serde_json::Value =
+                        serde_json::from_str(&contents).map_err(|e| {
+                            mlua::Error::runtime(format!(
+                                "failed to parse JSON schema in '{}': {}",
+                                full_path.display(),
+                                e
+                            ))
+                        })?;
+                    let validator = jsonschema::validator_for(&schema).map_err(|e| {
+                        mlua::Error::runtime(format!(
+                            "invalid JSON schema in '{}': {}",
+                            full_path.display(),
+                            e
+                        ))
+                    })?;
+                    let json: serde_json::Value = l.from_value(document)?;
+                    let errors = validator
+                        .iter_errors(&json)
+                        .map(|e| match e.instance_path().as_str() {
+                            "" => e.to_string(),
+                            path => format!("{path}: {e}"),
+                        })
+                        .collect::<Vec<_>>();
+                    l.create_sequence_from(errors)
+                }
+            })
+            .map_err(|e| eyre!("failed to create validate_schema function: {e}"))?;
+
+        // Checks usually match the same few patterns against every document, so the compiled
+        // patterns are kept for the lifetime of the Lua state, up to a limit.
+        let regexes = RegexCache::default();
         let matches = lua
-            .create_function(|_, (str, pattern): (mlua::String, mlua::String)| {
-                let regexp = regex::Regex::new(&pattern.to_str()?).map_err(|e| {
+            .create_function(move |_, args| regex_matches(&regexes, args))
+            .map_err(|e| eyre!("failed to create matches function: {e}"))?;
+
+        // Note that Lua sequences are converted to JSON arrays, so array indices in the path are
+        // zero-based, unlike in Lua itself.
+        let query = lua
+            .create_function(|l, (document, path): (mlua::Value, mlua::String)| {
+                let json_path = serde_json_path::JsonPath::parse(&path.to_str()?).map_err(|e| {
                     mlua::Error::runtime(format!(
-                        "invalid regex pattern '{}': {}",
-                        pattern.display(),
+                        "invalid JSONPath expression '{}': {}",
+                        path.display(),
                         e
                     ))
                 })?;
-                Ok(regexp.is_match(&str.to_str()?))
+                let json: serde_json::Value = l.from_value(document)?;
+                let nodes = json_path.query(&json).all();
+                match nodes.as_slice() {
+                    [] => Ok(mlua::Value::Nil),
+                    [node] => l.to_value(node),
+                    nodes => l.to_value(nodes),
+                }
             })
-            .map_err(|e| eyre!("failed to create matches function: {e}"))?;
+            .map_err(|e| eyre!("failed to create query function: {e}"))?;
+
+        let set_default_severity = lua
+            .create_function(|l, severity: mlua::String| {
+                let severity = CheckSeverity::parse(&severity.to_str()?).ok_or_else(|| {
+                    mlua::Error::runtime(format!("invalid severity level: {}", severity.display()))
+                })?;
+                l.set_app_data(DefaultSeverity(severity));
+                Ok(())
+            })
+            .map_err(|e| eyre!("failed to create set_default_severity function: {e}"))?;
+
+        // Like Go's `strings.Split`, an empty delimiter splits after each character.
+        let split = lua
+            .create_function(|l, (str, delimiter): (mlua::String, mlua::String)| {
+                let (str, delimiter) = (str.to_str()?, delimiter.to_str()?);
+                if delimiter.is_empty() {
+                    l.create_sequence_from(str.chars().map(String::from))
+                } else {
+                    l.create_sequence_from(str.split(&*delimiter))
+                }
+            })
+            .map_err(|e| eyre!("failed to create split function: {e}"))?;
+
+        let trim_space = lua
+            .create_function(|_, str: mlua::String| Ok(str.to_str()?.trim().to_string()))
+            .map_err(|e| eyre!("failed to create trim_space function: {e}"))?;
+
+        let starts_with = lua
+            .create_function(|_, (str, prefix): (mlua::String, mlua::String)| {
+                Ok(str.as_bytes().starts_with(&prefix.as_bytes()))
+            })
+            .map_err(|e| eyre!("failed to create starts_with function: {e}"))?;
+
+        let ends_with = lua
+            .create_function(|_, (str, suffix): (mlua::String, mlua::String)| {
+                Ok(str.as_bytes().ends_with(&suffix.as_bytes()))
+            })
+            .map_err(|e| eyre!("failed to create ends_with function: {e}"))?;
+
+        let now = lua
+            .create_function(|l, ()| {
+                let fixed = l.app_data_ref::<LuaOptions>().and_then(|o| o.now);
+                Ok(fixed.unwrap_or_else(|| chrono::Utc::now().timestamp()))
+            })
+            .map_err(|e| eyre!("failed to create now function: {e}"))?;
+
+        let parse_date = lua
+            .create_function(|_, (str, format): (mlua::String, Option<mlua::String>)| {
+                let str = str.to_str()?;
+                let timestamp = match format {
+                    Some(format) => parse_date(&str, &format.to_str()?),
+                    None => chrono::DateTime::parse_from_rfc3339(&str)
+                        .ok()
+                        .map(|d| d.timestamp()),
+                };
+                Ok(timestamp)
+            })
+            .map_err(|e| eyre!("failed to create parse_date function: {e}"))?;
+
+        // Values are compared by converting them to JSON, so integers and floats are never equal,
+        // even if they have the same value: `DeepEqual(1, 1.0)` is false. Likewise, `nil` equals
+        // JSON's `null`, and an empty table equals any other empty table.
+        let deep_equal = lua
+            .create_function(|l, (a, b): (mlua::Value, mlua::Value)| {
+                let a: serde_json::Value = l.from_value(a)?;
+                let b: serde_json::Value = l.from_value(b)?;
+                Ok(a == b)
+            })
+            .map_err(|e| eyre!("failed to create deep_equal function: {e}"))?;
+
+        // The same caveats as for `DeepEqual` apply to comparing the elements.
+        let contains = lua
+            .create_function(|l, (haystack, needle): (mlua::Value, mlua::Value)| {
+                let haystack: serde_json::Value = l.from_value(haystack)?;
+                let needle: serde_json::Value = l.from_value(needle)?;
+                match haystack {
+                    serde_json::Value::Array(values) => Ok(values.contains(&needle)),
+                    // An empty table is converted to an empty object.
+                    serde_json::Value::Object(values) if values.is_empty() => Ok(false),
+                    _ => Err(mlua::Error::runtime(
+                        "Contains expects a sequence table to search in",
+                    )),
+                }
+            })
+            .map_err(|e| eyre!("failed to create contains function: {e}"))?;
+
+        // Whether the value is a number without a fractional part, whether Lua holds it as an
+        // integer or as a float, so `2` and `2.0` from different formats are treated alike. Floats
+        // beyond the range of an integer, like `1e300`, don't count.
+        let is_integer = lua
+            .create_function(|_, value: mlua::Value| {
+                const RANGE: std::ops::Range<f64> = i64::MIN as f64..-(i64::MIN as f64);
+                Ok(match value {
+                    mlua::Value::Integer(_) => true,
+                    mlua::Value::Number(n) => RANGE.contains(&n) && n.fract() == 0.0,
+                    _ => false,
+                })
+            })
+            .map_err(|e| eyre!("failed to create is_integer function: {e}"))?;
+
+        // Numeric strings are parsed as an integer if they can be, or else as a float. Strings
+        // that aren't UTF-8 can't be numeric.
+        let as_number = lua
+            .create_function(|_, value: mlua::Value| {
+                Ok(match value {
+                    mlua::Value::Integer(_) | mlua::Value::Number(_) => value,
+                    mlua::Value::String(s) => {
+                        let Ok(s) = s.to_str() else {
+                            return Ok(mlua::Value::Nil);
+                        };
+                        let s = s.trim();
+                        match s.parse::<i64>() {
+                            Ok(i) => mlua::Value::Integer(i),
+                            Err(_) => s
+                                .parse::<f64>()
+                                .ok()
+                                .filter(|n| n.is_finite())
+                                .map_or(mlua::Value::Nil, mlua::Value::Number),
+                        }
+                    }
+                    _ => mlua::Value::Nil,
+                })
+            })
+            .map_err(|e| eyre!("failed to create as_number function: {e}"))?;
+
+        // Keys are escaped as described on `flatten_into`, so the paths can be split unambiguously.
+        let flatten = lua
+            .create_function(|l, table: mlua::Value| {
+                if !table.is_table() {
+                    return Err(mlua::Error::runtime(format!(
+                        "Flatten expects a table, got {}",
+                        table.type_name()
+                    )));
+                }
+                let value: serde_json::Value = l.from_value(table)?;
+                let mut flat = serde_json::Map::new();
+                flatten_into(&mut flat, None, value);
+                l.to_value(&flat)
+            })
+            .map_err(|e| eyre!("failed to create flatten function: {e}"))?;
+
+        let keys = lua
+            .create_function(|l, table: mlua::Value| {
+                let pairs = sorted_string_pairs(table, "Keys")?;
+                l.create_sequence_from(pairs.into_iter().map(|(k, _)| k))
+            })
+            .map_err(|e| eyre!("failed to create keys function: {e}"))?;
+
+        let values = lua
+            .create_function(|l, table: mlua::Value| {
+                let pairs = sorted_string_pairs(table, "Values")?;
+                l.create_sequence_from(pairs.into_iter().map(|(_, v)| v))
+            })
+            .map_err(|e| eyre!("failed to create values function: {e}"))?;
+
+        // This is only for debugging checks: it doesn't affect the findings, and is only shown when
+        // debug logging is enabled, e.g. with `RUST_LOG=checkonaut=debug`.
+        let log = lua
+            .create_function({
+                let source_path = source_path.clone();
+                move |l, (message, value): (mlua::String, Option<mlua::Value>)| {
+                    let check = source_path.display();
+                    let message = message.to_str()?;
+                    match value {
+                        Some(value) => {
+                            let value: serde_json::Value = l.from_value(value)?;
+                            debug!(%check, %value, "{message}");
+                        }
+                        None => debug!(%check, "{message}"),
+                    }
+                    Ok(())
+                }
+            })
+            .map_err(|e| eyre!("failed to create log function: {e}"))?;
+
+        // Only allowlisted variables are readable, so checks can't e.g. read secrets by accident.
+        let env = lua
+            .create_function(|l, name: mlua::String| {
+                let name = name.to_str()?;
+                let exposed = l
+                    .app_data_ref::<LuaOptions>()
+                    .is_some_and(|o| o.expose_env.iter().any(|e| *e == *name));
+                Ok(exposed.then(|| std::env::var(&*name).ok()).flatten())
+            })
+            .map_err(|e| eyre!("failed to create env function: {e}"))?;
+
+        // Both take an optional boolean selecting the URL-safe alphabet over the standard one.
+        let base64_encode = lua
+            .create_function(|_, (str, url_safe): (mlua::String, Option<bool>)| {
+                Ok(base64_engine(url_safe).encode(str.as_bytes()))
+            })
+            .map_err(|e| eyre!("failed to create base64_encode function: {e}"))?;
+
+        let base64_decode = lua
+            .create_function(|l, (str, url_safe): (mlua::String, Option<bool>)| {
+                let decoded = base64_engine(url_safe)
+                    .decode(str.as_bytes())
+                    .map_err(|e| mlua::Error::runtime(format!("invalid base64: {e}")))?;
+                l.create_string(decoded)
+            })
+            .map_err(|e| eyre!("failed to create base64_decode function: {e}"))?;
+
+        let semver_satisfies = lua
+            .create_function(|_, (version, requirement): (mlua::String, mlua::String)| {
+                let version = parse_semver(&version.to_str()?, "version")?;
+                let requirement = semver::VersionReq::parse(&requirement.to_str()?)
+                    .map_err(|e| mlua::Error::runtime(format!("invalid requirement: {e}")))?;
+                Ok(requirement.matches(&version))
+            })
+            .map_err(|e| eyre!("failed to create semver_satisfies function: {e}"))?;
+
+        let semver_compare = lua
+            .create_function(|_, (a, b): (mlua::String, mlua::String)| {
+                let a = parse_semver(&a.to_str()?, "first version")?;
+                let b = parse_semver(&b.to_str()?, "second version")?;
+                Ok(a.cmp(&b) as i8)
+            })
+            .map_err(|e| eyre!("failed to create semver_compare function: {e}"))?;
+
+        let error = finding_constructor(lua, CheckSeverity::Error)
+            .map_err(|e| eyre!("failed to create error function: {e}"))?;
+        let warn = finding_constructor(lua, CheckSeverity::Warning)
+            .map_err(|e| eyre!("failed to create warn function: {e}"))?;
 
         let module = lua
-            .create_table_from([("ReadJSON", read_json), ("Matches", matches)])
+            .create_table_from([
+                ("ReadJSON", read_json),
+                ("ReadFile", read_file),
+                ("Matches", matches),
+                ("Query", query),
+                ("ValidateSchema", validate_schema),
+                ("SetDefaultSeverity", set_default_severity),
+                ("Split", split),
+                ("TrimSpace", trim_space),
+                ("StartsWith", starts_with),
+                ("EndsWith", ends_with),
+                ("Now", now),
+                ("ParseDate", parse_date),
+                ("DeepEqual", deep_equal),
+                ("Contains", contains),
+                ("IsInteger", is_integer),
+                ("AsNumber", as_number),
+                ("Flatten", flatten),
+                ("Keys", keys),
+                ("Values", values),
+                ("Log", log),
+                ("Error", error),
+                ("Warn", warn),
+                ("Env", env),
+                ("Base64Encode", base64_encode),
+                ("Base64Decode", base64_decode),
+                ("SemverSatisfies", semver_satisfies),
+                ("SemverCompare", semver_compare),
+            ])
             .map_err(|e| eyre!("failed to create table for module: {e}"))?;
-        lua.register_module("@checkonaut", module)
+        lua.register_module("@checkonaut", &module)
             .map_err(|e| eyre!("failed to register checkonaut module: {e}"))?;
         debug!("loaded 'checkonaut' module for '{}'", self.path.display());
-        Ok(())
+        Ok(module)
     }
 }
 
-fn new_lua_for(path: &Path) -> Result<Lua> {
-    let lua = Lua::new();
-    update_package_path(&lua, path)?;
-    Ok(lua)
+/// A function of a module available to Lua, as documented for `checkonaut explain`.
+#[derive(Debug, Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    /// How the function is called, e.g. `Split(str, delimiter) -> {string}`.
+    pub signature: &'static str,
+    pub description: &'static str,
 }
 
-fn update_package_path(lua: &Lua, for_file: &Path) -> Result<()> {
-    let parent_str = for_file
-        .parent()
-        .and_then(|p| p.to_str())
-        .wrap_err_with(|| format!("path is not UTF-8: {}", for_file.display()))?;
-
-    // TODO: Can we set this with a scope so that we don't pollute the global state?
-    //  We could use the _ENV variable...
-    lua.globals()
-        .set("__CHECKONAUT_FILE_PATH", parent_str)
-        .map_err(|e| eyre!("failed to set global in Lua: {e}"))?;
-    lua.load(r#"
-        package.path = package.path .. ";" .. __CHECKONAUT_FILE_PATH .. "/?.lua;" .. __CHECKONAUT_FILE_PATH .. "/?/init.lua"
-"#).set_name("=checkonaut_update_package_path").exec().map_err(|e| {
-            eyre!( "failed to update package.path in Lua for file '{}': {e}", for_file.display())
+/// Adds the leaves of the value to the flat map for `Flatten`, keyed by their dotted path below the
+/// prefix.
+///
+/// Array elements are keyed by their 0-based index. Dots and backslashes in keys are escaped with a
+/// backslash, so `{ ["a.b"] = { c = 1 } }` becomes `a\.b.c`. Empty tables are leaves themselves,
+/// so they still show up in the flat map.
+fn flatten_into(
+    flat: &mut serde_json::Map<String, serde_json::Value>,
+    prefix: Option<String>,
+    value: serde_json::Value,
+) {
+    let path = |key: &str| match &prefix {
+        Some(prefix) => format!("{prefix}.{key}"),
+        None => key.to_string(),
+    };
+    match value {
+        serde_json::Value::Object(values) if !values.is_empty() => {
+            for (key, value) in values {
+                let key = key.replace('\\', "\\\\").replace('.', "\\.");
+                flatten_into(flat, Some(path(&key)), value);
+            }
+        }
+        serde_json::Value::Array(values) if !values.is_empty() => {
+            for (index, value) in values.into_iter().enumerate() {
+                flatten_into(flat, Some(path(&index.to_string())), value);
+            }
+        }
+        // An empty table at the root has no leaves at all.
+        value => {
+            if let Some(prefix) = prefix {
+                flat.insert(prefix, value);
+            }
+        }
+    }
+}
+
+/// The pairs of the table with string keys, sorted by key, for `Keys` and `Values`.
+///
+/// Other keys, e.g. the indices of a sequence, are skipped.
+fn sorted_string_pairs(
+    table: mlua::Value,
+    function: &str,
+) -> mlua::Result<Vec<(mlua::String, mlua::Value)>> {
+    let mlua::Value::Table(table) = table else {
+        return Err(mlua::Error::runtime(format!(
+            "{function} expects a table, got {}",
+            table.type_name()
+        )));
+    };
+    let mut pairs = Vec::new();
+    for pair in table.pairs::<mlua::Value, mlua::Value>() {
+        if let (mlua::Value::String(k), v) = pair? {
+            pairs.push((k, v));
+        }
+    }
+    pairs.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(&b.as_bytes()));
+    Ok(pairs)
+}
+
+/// The compiled patterns of `Matches`, by pattern and flags.
+type RegexCache = RefCell<HashMap<(String, String), regex::Regex>>;
+
+/// How many compiled patterns a [`RegexCache`] holds. Once full, it's cleared, so checks that
+/// build patterns from the data can't grow it without bound.
+const REGEX_CACHE_SIZE: usize = 256;
+
+/// The implementation of `Matches`: with a sequence of patterns, this is whether any of them
+/// matches.
+fn regex_matches(
+    regexes: &RegexCache,
+    (str, patterns, flags): (mlua::String, mlua::Value, Option<mlua::String>),
+) -> mlua::Result<bool> {
+    let flags = match &flags {
+        Some(flags) => flags.to_str()?.to_string(),
+        None => String::new(),
+    };
+    if let Some(flag) = flags.chars().find(|c| !REGEX_FLAGS.contains(*c)) {
+        return Err(mlua::Error::runtime(format!(
+            "invalid regex flag '{flag}'; expected any of i, m, s, and x"
+        )));
+    }
+    let compile = |pattern: &mlua::String, which: &str| {
+        let key = (pattern.to_str()?.to_string(), flags.clone());
+        if let Some(regex) = regexes.borrow().get(&key) {
+            return Ok(regex.clone());
+        }
+        let regex = compile_regex(&key.0, &flags).map_err(|e| {
+            mlua::Error::runtime(format!(
+                "invalid regex pattern{which} '{}': {}",
+                pattern.display(),
+                e
+            ))
         })?;
+        let mut regexes = regexes.borrow_mut();
+        if regexes.len() >= REGEX_CACHE_SIZE {
+            regexes.clear();
+        }
+        regexes.insert(key, regex.clone());
+        Ok::<_, mlua::Error>(regex)
+    };
+    let str = str.to_str()?;
+    match patterns {
+        mlua::Value::String(pattern) => Ok(compile(&pattern, "")?.is_match(&str)),
+        mlua::Value::Table(patterns) => {
+            // Every pattern is compiled, so invalid ones are found even after a match.
+            let mut matched = false;
+            for (index, pattern) in patterns.sequence_values::<mlua::String>().enumerate() {
+                let which = format!(" #{}", index + 1);
+                matched |= compile(&pattern?, &which)?.is_match(&str);
+            }
+            Ok(matched)
+        }
+        other => Err(mlua::Error::runtime(format!(
+            "expected a pattern string or a table of them, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// The flags `Matches` accepts, as in the inline flags of the `regex` crate.
+const REGEX_FLAGS: &str = "imsx";
+
+/// Compiles the pattern with the flags, which must all be in [`REGEX_FLAGS`].
+fn compile_regex(pattern: &str, flags: &str) -> Result<regex::Regex, regex::Error> {
+    let mut builder = regex::RegexBuilder::new(pattern);
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            _ => unreachable!("unchecked regex flag '{flag}'"),
+        };
+    }
+    builder.build()
+}
+
+/// Parses a semantic version, naming the argument it came from if it is invalid.
+fn parse_semver(version: &str, argument: &str) -> mlua::Result<semver::Version> {
+    semver::Version::parse(version)
+        .map_err(|e| mlua::Error::runtime(format!("invalid {argument} '{version}': {e}")))
+}
+
+/// The base64 engine for the standard alphabet, or the URL-safe one if asked for.
+fn base64_engine(url_safe: Option<bool>) -> &'static base64::engine::GeneralPurpose {
+    if url_safe.unwrap_or(false) {
+        &base64::engine::general_purpose::URL_SAFE
+    } else {
+        &base64::engine::general_purpose::STANDARD
+    }
+}
+
+/// Creates a function that constructs a finding table of the given severity, e.g.
+/// `Error("bad", { id = "rule" })` is `{ message = "bad", severity = "error", id = "rule" }`.
+fn finding_constructor(lua: &Lua, severity: CheckSeverity) -> mlua::Result<Function> {
+    lua.create_function(
+        move |l, (message, fields): (mlua::String, Option<mlua::Table>)| {
+            let finding = l.create_table()?;
+            if let Some(fields) = fields {
+                for pair in fields.pairs::<mlua::Value, mlua::Value>() {
+                    let (key, value) = pair?;
+                    finding.set(key, value)?;
+                }
+            }
+            finding.set("message", message)?;
+            finding.set("severity", severity.as_str())?;
+            Ok(finding)
+        },
+    )
+}
+
+/// Parses a date with the given `strftime`-like format into a Unix timestamp.
+///
+/// The format may describe a date and time with a time zone, a date and time (assumed to be in
+/// UTC), or a date alone (assumed to be midnight UTC). Returns `None` if the date doesn't parse.
+fn parse_date(date: &str, format: &str) -> Option<i64> {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+    if let Ok(date) = DateTime::parse_from_str(date, format) {
+        Some(date.timestamp())
+    } else if let Ok(date) = NaiveDateTime::parse_from_str(date, format) {
+        Some(date.and_utc().timestamp())
+    } else {
+        NaiveDate::parse_from_str(date, format)
+            .ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .map(|d| d.and_utc().timestamp())
+    }
+}
+
+/// The default maximum size of a file read with `ReadFile`, so a check can't accidentally load an
+/// enormous file into memory.
+const READ_FILE_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// Resolves a path relative to the directory of the given source file.
+fn resolve_relative_path(source_path: &Path, path: &str) -> mlua::Result<PathBuf> {
+    let parent = source_path.parent().ok_or_else(|| {
+        mlua::Error::runtime(format!(
+            "cannot determine parent directory of '{}'",
+            source_path.display(),
+        ))
+    })?;
+    Ok(parent.join(path))
+}
+
+/// Reads a file relative to the directory of the given source file.
+///
+/// Returns the resolved path along with the file contents.
+fn read_relative_file(source_path: &Path, path: &str) -> mlua::Result<(PathBuf, String)> {
+    let full_path = resolve_relative_path(source_path, path)?;
+    let contents = std::fs::read_to_string(&full_path).map_err(|e| {
+        mlua::Error::runtime(format!("failed to read '{}': {}", full_path.display(), e))
+    })?;
+    Ok((full_path, contents))
+}
+
+fn update_package_path(lua: &Lua, for_file: &Path) -> Result<()> {
+    let parent = for_file.parent().unwrap_or(Path::new(""));
+    let lua_paths = lua
+        .app_data_ref::<LuaOptions>()
+        .map(|o| o.lua_path.clone())
+        .unwrap_or_default();
+
+    let package = lua
+        .globals()
+        .get::<mlua::Table>("package")
+        .map_err(|e| eyre!("failed to get package table in Lua: {e}"))?;
+    let mut path = package
+        .get::<String>("path")
+        .map_err(|e| eyre!("failed to get package.path in Lua: {e}"))?;
+    for dir in std::iter::once(parent).chain(lua_paths.iter().map(PathBuf::as_path)) {
+        let dir = dir
+            .to_str()
+            .wrap_err_with(|| format!("path is not UTF-8: {}", dir.display()))?;
+        path.push_str(&format!(";{dir}/?.lua;{dir}/?/init.lua"));
+    }
+    package.set("path", path).map_err(|e| {
+        eyre!(
+            "failed to update package.path in Lua for file '{}': {e}",
+            for_file.display()
+        )
+    })?;
 
     Ok(())
 }
 
+/// The message of a Lua runtime error, including one raised from within a Rust function.
+fn runtime_error(error: &mlua::Error) -> Option<&str> {
+    match error {
+        mlua::Error::RuntimeError(message) => Some(message),
+        mlua::Error::CallbackError { cause, .. } => runtime_error(cause),
+        _ => None,
+    }
+}
+
+/// The outcome of running the test functions of a test file.
+#[derive(Debug, Clone, Default)]
+pub struct TestOutcome {
+    /// How many test functions passed.
+    pub passed: usize,
+    /// The failure messages of the test functions that failed.
+    pub failures: Vec<String>,
+}
+
 /// The severity of a check finding, as returned by `Check` functions.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CheckSeverity {
+    /// An error that makes any further checking of the data file pointless. The remaining checks
+    /// are skipped, and the run always fails.
+    Fatal,
     Error,
     Warning,
 }
 
+impl CheckSeverity {
+    /// Parses a severity as written in Lua, e.g. `"warning"`.
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "fatal" => Some(Self::Fatal),
+            "error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            _ => None,
+        }
+    }
+
+    /// The severity as written in Lua; the inverse of [`CheckSeverity::parse`].
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Fatal => "fatal",
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// The severity given to findings that don't specify one, as set by `SetDefaultSeverity`.
+/// When unset, findings default to [`CheckSeverity::Error`].
+#[derive(Debug, Clone, Copy)]
+struct DefaultSeverity(CheckSeverity);
+
 /// Intermediate result type returned by `Check` functions.
 #[derive(Debug, Clone)]
 enum CheckResult {
@@ -218,6 +1403,9 @@ enum CheckResult {
     Error {
         severity: Option<CheckSeverity>,
         error: String,
+        location: Option<Location>,
+        id: Option<String>,
+        fix: Option<String>,
     },
     /// A wrapper around multiple error results (or potentially nils).
     Many {
@@ -227,18 +1415,29 @@ enum CheckResult {
 }
 
 impl CheckResult {
-    fn flatten(self) -> Vec<CheckError> {
+    fn flatten(self, default_severity: CheckSeverity) -> Vec<CheckError> {
         let mut acc = Vec::new();
-        self.flatten_internal(&mut acc, CheckSeverity::Error);
+        self.flatten_internal(&mut acc, default_severity);
         acc
     }
 
     fn flatten_internal(self, acc: &mut Vec<CheckError>, inherited_severity: CheckSeverity) {
         match self {
             Self::Nil => {}
-            Self::Error { severity, error } => acc.push(CheckError {
+            Self::Error {
+                severity,
+                error,
+                location,
+                id,
+                fix,
+            } => acc.push(CheckError {
                 severity: severity.unwrap_or(inherited_severity),
                 error,
+                location,
+                id,
+                document_index: None,
+                function: None,
+                fix,
             }),
             Self::Many { severity, results } => {
                 let severity = severity.unwrap_or(inherited_severity);
@@ -251,7 +1450,7 @@ impl CheckResult {
 }
 
 impl FromLua for CheckResult {
-    fn from_lua(value: mlua::Value, _lua: &Lua) -> mlua::Result<Self> {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
         match value {
             mlua::Value::Nil => Ok(CheckResult::Nil),
 
@@ -260,6 +1459,9 @@ impl FromLua for CheckResult {
                 Ok(CheckResult::Error {
                     severity: None,
                     error,
+                    location: None,
+                    id: None,
+                    fix: None,
                 })
             }
 
@@ -268,7 +1470,8 @@ impl FromLua for CheckResult {
                 //   * We can have a sequence of errors (i.e., a vec).
                 //   * We can have a dictionary with a "message" and optionally "severity" (i.e., a
                 //     single error). The message can be either a string, or a vec of strings (or
-                //     even nil).
+                //     even nil). It may also carry a location via "line", "column", and "path",
+                //     a stable rule identifier via "id", and a suggested fix via "fix".
 
                 if !table.contains_key("message")? {
                     // If we have no "message" key, we'll assume it's a sequence of errors.
@@ -280,8 +1483,11 @@ impl FromLua for CheckResult {
                             mlua::Value::String(s) => results.push(CheckResult::Error {
                                 severity: None,
                                 error: s.to_str()?.to_string(),
+                                location: None,
+                                id: None,
+                                fix: None,
                             }),
-                            otherwise => results.push(CheckResult::from_lua(otherwise, _lua)?),
+                            otherwise => results.push(CheckResult::from_lua(otherwise, lua)?),
                         }
                     }
                     Ok(Self::Many {
@@ -292,18 +1498,38 @@ impl FromLua for CheckResult {
                     let severity: Option<String> = table.get("severity")?;
                     let severity = match severity.as_deref() {
                         None => None,
-                        Some("error") => Some(CheckSeverity::Error),
-                        Some("warning") => Some(CheckSeverity::Warning),
-                        Some(other) => {
-                            return Err(mlua::Error::FromLuaConversionError {
+                        Some(s) => Some(CheckSeverity::parse(s).ok_or_else(|| {
+                            mlua::Error::FromLuaConversionError {
                                 from: "string",
                                 to: "CheckSeverity".into(),
-                                message: Some(format!("invalid severity level: {}", other)),
-                            });
-                        }
+                                message: Some(format!("invalid severity level: {}", s)),
+                            }
+                        })?),
                     };
                     let error: String = table.get("message")?;
-                    Ok(CheckResult::Error { severity, error })
+                    let location = Location {
+                        line: table.get("line")?,
+                        column: table.get("column")?,
+                        path: table.get("path")?,
+                    };
+                    let location = (!location.is_empty()).then_some(location);
+                    let id: Option<String> = table.get("id")?;
+                    // A structured fix is kept as JSON, for tooling to interpret.
+                    let fix = match table.get::<mlua::Value>("fix")? {
+                        mlua::Value::Nil => None,
+                        mlua::Value::String(s) => Some(s.to_str()?.to_string()),
+                        fix => {
+                            let fix: serde_json::Value = lua.from_value(fix)?;
+                            Some(fix.to_string())
+                        }
+                    };
+                    Ok(CheckResult::Error {
+                        severity,
+                        error,
+                        location,
+                        id,
+                        fix,
+                    })
                 }
             }
             _ => Err(mlua::Error::FromLuaConversionError {
@@ -316,14 +1542,84 @@ impl FromLua for CheckResult {
 }
 
 /// A single check error.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CheckError {
     pub severity: CheckSeverity,
     pub error: String,
+    /// Where in the data file the error was found, if the check told us.
+    pub location: Option<Location>,
+    /// A stable, machine-readable identifier of the rule that produced this error, if the check
+    /// gave one.
+    pub id: Option<String>,
+    /// The zero-based index of the document within its data file.
+    /// This is only set when the data file holds more than one document.
+    pub document_index: Option<usize>,
+    /// The name of the check function that produced this error, e.g. `Check_Naming`.
+    /// This is only set for functions other than the plain check function.
+    #[serde(default)]
+    pub function: Option<String>,
+    /// A suggested fix for the error, if the check gave one. This is either free-form text, or
+    /// JSON if the check gave a table.
+    #[serde(default)]
+    pub fix: Option<String>,
 }
 
 impl fmt::Display for CheckError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "[{:?}] {}", self.severity, self.error)
+        write!(f, "[{:?}] ", self.severity)?;
+        if let Some(id) = &self.id {
+            write!(f, "[{id}] ")?;
+        }
+        write!(f, "{}", self.error)?;
+        let mut details = Vec::with_capacity(2);
+        if let Some(index) = self.document_index {
+            details.push(format!("document #{index}"));
+        }
+        if let Some(location) = &self.location {
+            details.push(format!("at {location}"));
+        }
+        if let Some(function) = &self.function {
+            details.push(format!("from {function}"));
+        }
+        if let Some(fix) = &self.fix {
+            details.push(format!("suggested fix: {fix}"));
+        }
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The location of a check error within a data file.
+///
+/// Lines and columns are 1-based, as reported by the check. The path is free-form, but should
+/// typically be a JSON pointer (e.g. `/spec/containers/0/image`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub path: Option<String>,
+}
+
+impl Location {
+    fn is_empty(&self) -> bool {
+        self.line.is_none() && self.column.is_none() && self.path.is_none()
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::with_capacity(3);
+        if let Some(path) = &self.path {
+            parts.push(format!("path {path}"));
+        }
+        if let Some(line) = self.line {
+            parts.push(format!("line {line}"));
+        }
+        if let Some(column) = self.column {
+            parts.push(format!("column {column}"));
+        }
+        write!(f, "{}", parts.join(", "))
     }
 }
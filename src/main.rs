@@ -1,14 +1,42 @@
-use clap::Parser;
+use checkonaut::{CancellationToken, Cli};
+use clap::{ColorChoice, Parser};
 use eyre::{Context, Result};
+use std::process::ExitCode;
 
-fn main() -> Result<()> {
-    color_eyre::install()?;
-    let cli = checkonaut::Cli::parse();
-    tracing_subscriber::fmt()
-        .with_env_filter(cli.logger.as_deref().unwrap_or("warn,checkonaut=info"))
+fn main() -> ExitCode {
+    let cancellation = CancellationToken::default();
+    let cli = Cli::parse().cancellation(cancellation.clone());
+    let result = set_up(&cli, cancellation).and_then(|()| cli.run());
+    if let Err(error) = &result {
+        eprintln!("Error: {error:?}");
+    }
+    checkonaut::exit_code(&result)
+}
+
+fn set_up(cli: &Cli, cancellation: CancellationToken) -> Result<()> {
+    let mut hook = color_eyre::config::HookBuilder::new();
+    if cli.color == ColorChoice::Never {
+        hook = hook.theme(color_eyre::config::Theme::new());
+    }
+    hook.install()?;
+    // The first Ctrl-C stops the run gracefully; a second one exits immediately.
+    ctrlc::set_handler(move || {
+        if cancellation.cancel() {
+            std::process::exit(130);
+        }
+    })
+    .wrap_err("failed to set up Ctrl-C handler")?;
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(cli.log_filter())
+        // Logs go to stderr, so stdout can be used for machine-readable output.
+        .with_writer(std::io::stderr);
+    let subscriber = match cli.ansi() {
+        Some(ansi) => subscriber.with_ansi(ansi),
+        None => subscriber,
+    };
+    subscriber
         .try_init()
         .map_err(|e| eyre::eyre!(e))
         .wrap_err("failed to set up logging")?;
-    cli.run()?;
     Ok(())
 }
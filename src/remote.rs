@@ -0,0 +1,76 @@
+use crate::file::{DataExtensions, DataFormat, InMemoryData};
+use eyre::{Context, Result, ensure};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::debug;
+
+/// Whether the input is an HTTP(S) URL, rather than a path.
+pub fn is_url(input: &Path) -> bool {
+    input
+        .to_str()
+        .is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// Fetches all the URLs, keyed by the URL as given.
+///
+/// The format of each is detected from the URL's extension, or else the `Content-Type` header.
+/// Each fetch must finish within the timeout, and respond with a 2xx status.
+pub fn fetch_all(
+    urls: &[PathBuf],
+    timeout: Duration,
+    data_extensions: &DataExtensions,
+) -> Result<HashMap<PathBuf, InMemoryData>> {
+    if urls.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let client = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()
+        .wrap_err("failed to create HTTP client")?;
+    urls.par_iter()
+        .map(|url| {
+            let data = fetch(&client, url, data_extensions)
+                .wrap_err_with(|| format!("failed to fetch data from URL: {}", url.display()))?;
+            Ok((url.clone(), data))
+        })
+        .collect()
+}
+
+fn fetch(
+    client: &reqwest::blocking::Client,
+    url: &Path,
+    data_extensions: &DataExtensions,
+) -> Result<InMemoryData> {
+    let url = url.to_str().unwrap_or_default();
+    let response = client.get(url).send().wrap_err("request failed")?;
+    let status = response.status();
+    ensure!(status.is_success(), "unexpected HTTP status: {status}");
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let contents = response
+        .bytes()
+        .wrap_err("failed to read response body")?
+        .to_vec();
+    // The query and fragment aren't part of the name the extension is taken from.
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let format = data_extensions.format_of(Path::new(path)).or_else(|| {
+        content_type
+            .as_deref()
+            .and_then(DataFormat::from_media_type)
+    });
+    debug!(
+        url,
+        bytes = contents.len(),
+        ?content_type,
+        ?format,
+        "fetched data from URL"
+    );
+    Ok(InMemoryData { contents, format })
+}
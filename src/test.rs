@@ -1,82 +1,209 @@
 use crate::{
-    file::{FileSearchResult, FileSearcher},
-    lua::SourceCode,
+    CancelledError, FindingsError,
+    check::{changed_files, plural},
+    config::Config,
+    file::{FileSearchResult, FileSearcher, expand_globs},
+    lua::{LuaOptions, SourceCode, TestOutcome},
 };
 use clap::Args;
 use eyre::{Context, Result, ensure};
-use mlua::Lua;
+use globset::Glob;
 use rayon::prelude::*;
-use std::path::PathBuf;
-use tracing::{error, info};
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info};
 
 #[derive(Debug, Args)]
 pub struct Test {
     /// The check test files or directories to test.
     /// We only process files ending in `_test.lua`.
     ///
-    /// Files starting with a period (`.`) are ignored by default.
+    /// Files starting with a period (`.`) are ignored by default. Directories starting with one are
+    /// walked, unless `--dotdirs=false` is given.
+    ///
+    /// Glob patterns (e.g. `checks/**/*_test.lua`) are expanded, even if the shell doesn't.
     #[arg(default_value = ".")]
     input: Vec<PathBuf>,
 
     /// Enable processing of files starting with a period.
+    ///
+    /// Pass `--dotfiles=false` to turn this off when the configuration enables it.
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    dotfiles: Option<bool>,
+
+    /// Whether to descend into directories starting with a period, e.g. `.github`. They are by
+    /// default; pass `--dotdirs=false` to skip them, e.g. to stay out of `.git`.
+    ///
+    /// This is independent of `--dotfiles`: files starting with a period are still ignored within
+    /// these directories, unless `--dotfiles` is given as well.
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    dotdirs: Option<bool>,
+
+    /// Skip files and directories matching this glob, e.g. `vendor/**` or `*.generated.json`.
+    ///
+    /// The patterns are matched as if they were in a `.checkonautignore` file in every input:
+    /// against the path within the input, and against the plain file name.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<Glob>,
+
+    /// Only run test functions whose name contains this.
     #[arg(long)]
-    dotfiles: bool,
+    filter: Option<String>,
+
+    /// Only run test files that differ from this git ref, e.g. `origin/main`, or whose check file
+    /// (the test file without `_test`) does.
+    ///
+    /// The changes are found with `git diff` in the repository containing the first input.
+    #[arg(long, value_name = "REF")]
+    only_changed_checks: Option<String>,
+
+    /// How many test files to run at once. By default, this is the same as `--rayon-threads`.
+    ///
+    /// With 1, the test files run one after another, in sorted order.
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Don't log each test failure; only print the totals to stdout, as
+    /// `tests=N passed=N failed=N`.
+    ///
+    /// Any failure still fails the run.
+    #[arg(long)]
+    count_only: bool,
+
+    #[command(flatten)]
+    pub(crate) lua: LuaOptions,
 }
 
 impl Test {
+    /// Fills in the flags that weren't given on the command line from the configuration.
+    pub fn apply_config(&mut self, config: &Config) -> Result<()> {
+        self.dotfiles = self.dotfiles.or(config.dotfiles);
+        self.dotdirs = self.dotdirs.or(config.dotdirs);
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude_globs()?;
+        }
+        self.lua.apply_config(config);
+        Ok(())
+    }
+
     pub fn run(self) -> Result<()> {
+        self.lua.report();
+        let input = expand_globs(self.input).wrap_err("failed to expand input paths")?;
         let FileSearchResult {
             check_files: _,
-            test_files,
+            mut test_files,
             data_files: _,
         } = FileSearcher::default()
-            .include_dotfiles(self.dotfiles)
-            .include_dotdirs(self.dotfiles)
+            .include_dotfiles(self.dotfiles.unwrap_or(false))
+            .include_dotdirs(self.dotdirs.unwrap_or(true))
+            .exclude(self.exclude.clone())
             .include_test_files(true)
-            .search(self.input.into_par_iter())
+            .search(input.clone().into_par_iter())
             .wrap_err("failed to search input paths for relevant files")?;
+        if let Some(base) = &self.only_changed_checks {
+            let within = input.first().map_or(Path::new("."), PathBuf::as_path);
+            let changed = changed_files(base, within)?;
+            let is_changed =
+                |path: &Path| std::fs::canonicalize(path).is_ok_and(|p| changed.contains(&p));
+            test_files.retain(|path| {
+                let name = path.file_name().map(|n| n.to_string_lossy());
+                let check = name
+                    .as_deref()
+                    .and_then(|n| n.strip_suffix("_test.lua"))
+                    .map(|stem| path.with_file_name(format!("{stem}.lua")));
+                is_changed(path) || check.is_some_and(|c| is_changed(&c))
+            });
+            debug!(
+                base,
+                count = test_files.len(),
+                "restricted test files to changed files"
+            );
+        }
 
         #[derive(Debug, Clone)]
         struct TestResult {
             file: PathBuf,
-            errors: Vec<String>,
+            outcome: TestOutcome,
         }
-        let mut results = test_files
-            .into_par_iter()
-            .map(|file| {
-                let f2 = file.clone();
-                Ok(TestResult {
-                    errors: test_file(file).wrap_err_with(|| {
-                        format!("while testing file {:?}", f2.to_string_lossy())
-                    })?,
-                    file: f2,
-                })
-            })
-            .filter(|r| match r {
-                Err(_) => true,
-                Ok(res) => !res.errors.is_empty(),
+        let run_file = |file: PathBuf| {
+            let f2 = file.clone();
+            Ok(TestResult {
+                outcome: test_file(file, &self.lua, self.filter.as_deref())
+                    .wrap_err_with(|| format!("while testing file {:?}", f2.to_string_lossy()))?,
+                file: f2,
             })
-            .collect::<Result<Vec<_>>>()?;
+        };
+        let results = match self.jobs {
+            // Rayon doesn't promise an order even with a single thread, so don't use it at all.
+            Some(1) => test_files
+                .into_iter()
+                .map(run_file)
+                .collect::<Result<Vec<_>>>(),
+            Some(jobs) => rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs.into())
+                .build()
+                .wrap_err("failed to set up thread pool for tests")?
+                .install(|| test_files.into_par_iter().map(run_file).collect()),
+            None => test_files.into_par_iter().map(run_file).collect(),
+        };
+        // Tests aborted by the cancellation would otherwise be reported as failures.
+        ensure!(!self.lua.is_cancelled(), CancelledError);
+        let mut results = results?;
         results.sort_unstable_by_key(|r| r.file.clone());
+        let mut passed = 0;
+        let mut failed = 0;
         for res in &results {
-            for error in &res.errors {
+            passed += res.outcome.passed;
+            failed += res.outcome.failures.len();
+            if self.count_only {
+                continue;
+            }
+            for error in &res.outcome.failures {
                 error!(file = ?res.file, %error, "test failure");
             }
         }
-        ensure!(results.is_empty(), "one or more tests failed");
+        info!(
+            files = results.len(),
+            tests = passed + failed,
+            passed,
+            failed,
+            "ran {} in {}: {} passed, {} failed",
+            plural(passed + failed, "test"),
+            plural(results.len(), "file"),
+            passed,
+            failed,
+        );
+        if self.count_only {
+            println!("tests={} passed={passed} failed={failed}", passed + failed);
+        }
+        ensure!(
+            failed == 0,
+            FindingsError("one or more tests failed".to_string())
+        );
         info!("no test failures detected");
         Ok(())
     }
 }
 
-fn test_file(path: PathBuf) -> Result<Vec<String>> {
+fn test_file(path: PathBuf, options: &LuaOptions, filter: Option<&str>) -> Result<TestOutcome> {
     let source = SourceCode::read(&path).wrap_err("failed to read test source file")?;
-    let lua = Lua::new();
+    let lua = options.new_lua()?;
     source
-        .load_into(&lua)
+        .load_test_into(&lua)
         .wrap_err("failed to load source code into Lua")?;
 
     source
-        .call_test_functions(&lua)
+        .call_test_functions(&lua, |name| filter.is_none_or(|f| name.contains(f)))
         .wrap_err("failed to run test functions")
 }